@@ -9,28 +9,36 @@ use criterion::Criterion;
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("character insert", |b| {
         let mut doc = Doc::new();
+        let string_id = doc.next_id();
+        let timestamp = doc.next_timestamp();
         doc.update(DocOp {
-            timestamp: 1,
+            timestamp,
             edits: vec![
-                tree::Edit::TextCreate { id: Id { num: 1 } },
-                tree::Edit::MapInsert {
-                    parent: ROOT_ID,
+                json::Edit::TextCreate {
+                    id: json::StringRef(string_id.clone()),
+                },
+                json::Edit::MapInsert {
+                    parent: json::ObjectRef(ROOT_ID),
                     key: "my key".to_string(),
-                    item: tree::Value::Collection(Id { num: 1 }),
+                    item: json::Value::String(json::StringRef(string_id.clone())),
                 },
             ],
         });
 
-        let mut i = 2;
+        let mut prev = string_id;
+        let mut i = 0;
         b.iter(|| {
+            let id = doc.next_id();
+            let timestamp = doc.next_timestamp();
             doc.update(DocOp {
-                timestamp: i as u64,
-                edits: vec![black_box(tree::Edit::TextInsert {
-                    prev: Id { num: i - 1 },
-                    id: Id { num: i },
+                timestamp,
+                edits: vec![black_box(json::Edit::TextInsert {
+                    index: json::StringIndex(prev.clone()),
+                    id: json::StringIndex(id.clone()),
                     character: if i % 2 == 0 { 'a' } else { 'b' },
                 })],
             });
+            prev = id;
             i += 1;
         });
         black_box(doc);
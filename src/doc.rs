@@ -1,19 +1,74 @@
 use crate::json;
 use crate::opset;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use uuid::Uuid;
 
 const CACHE_GAP: usize = 10;
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// Identifies a value or character in a document. Ids are minted by a single replica, so two
+/// replicas can never accidentally create the same id while offline.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Id {
-    pub num: usize,
+    /// The replica that created this id.
+    pub site: SiteId,
+    /// Per-replica counter; every id created by a replica has a different `num`.
+    pub num: u64,
 }
 
-pub const ROOT_ID: Id = Id { num: 0 };
+impl json::SequentialId for Id {
+    fn checked_successor(&self, n: usize) -> Option<Id> {
+        Some(Id {
+            site: self.site,
+            num: self.num.checked_add(n as u64)?,
+        })
+    }
+
+    fn steps_from(&self, start: &Id) -> Option<usize> {
+        if self.site != start.site || self.num < start.num {
+            return None;
+        }
+        Some((self.num - start.num) as usize)
+    }
+}
+
+/// Id of the root object of every `Doc`. Ids allocated by `Doc::next_id` never collide with this.
+pub const ROOT_ID: Id = Id {
+    site: SiteId([0; 16]),
+    num: 0,
+};
+
+/// Uniquely identifies a replica of a document. Used to break ties between `Timestamp`s that have
+/// the same counter value, and to keep ids created by different replicas distinct. The bytes are
+/// those of a UUID; they're stored raw so that `ROOT_ID` can be a constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SiteId(pub [u8; 16]);
+
+impl SiteId {
+    /// Generates a new random site id.
+    pub fn new_random() -> SiteId {
+        SiteId::from(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for SiteId {
+    fn from(uuid: Uuid) -> SiteId {
+        SiteId(*uuid.as_bytes())
+    }
+}
+
+/// A Lamport timestamp. Timestamps are ordered first by `counter`, and then by `site`, so that two
+/// ops created concurrently at different sites always have a consistent total order, even if they
+/// happened to pick the same counter value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub counter: u64,
+    pub site: SiteId,
+}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DocOp {
-    pub timestamp: u64,
+    pub timestamp: Timestamp,
     pub edits: Vec<json::Edit<Id>>,
 }
 impl PartialOrd for DocOp {
@@ -23,39 +78,617 @@ impl PartialOrd for DocOp {
 }
 impl Ord for DocOp {
     fn cmp(&self, other: &DocOp) -> Ordering {
-        // TODO fix this ordering
         self.timestamp.cmp(&other.timestamp)
     }
 }
 
+/// Describes why a `DocOp` could not be applied. Ops are applied atomically, so none of the op's
+/// edits took effect.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocOpError {
+    /// Index into `DocOp::edits` of the edit that failed.
+    pub edit_index: usize,
+    pub error: json::TreeError,
+}
+
+/// Describes what happened during a call to `Doc::update` or `Doc::update_from_iter`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdateReport {
+    /// Ops that were newly added to the document.
+    pub applied: Vec<Timestamp>,
+    /// Ops that were already in the document, and were ignored.
+    pub duplicates: Vec<Timestamp>,
+    /// Ops that were rejected because a different op with the same timestamp was already in the
+    /// document. This indicates a misbehaving replica.
+    pub conflicts: Vec<Timestamp>,
+    /// Ops that failed to apply during this update, and had not already failed in the same way.
+    /// Besides newly applied ops, this can include older ops that now fail because an op with an
+    /// earlier timestamp arrived.
+    pub failures: Vec<(Timestamp, DocOpError)>,
+    /// Ops that were refused without being added to the document, because their timestamp
+    /// counter is `u64::MAX`, or they use an id from this replica with `num` `u64::MAX`.
+    /// Accepting such an op would leave this replica with no timestamps or ids left to give its
+    /// own ops.
+    pub rejected: Vec<Timestamp>,
+}
+
 impl opset::Operation<json::Tree<Id>> for DocOp {
-    fn apply(&self, tree: &mut json::Tree<Id>) {
-        for edit in &self.edits {
-            let _ = tree.update(edit);
-        }
+    type Error = DocOpError;
+
+    fn apply(&self, tree: &mut json::Tree<Id>) -> Result<(), DocOpError> {
+        tree.atomically(|tree| {
+            for (edit_index, edit) in self.edits.iter().enumerate() {
+                tree.update(edit)
+                    .map_err(|error| DocOpError { edit_index, error })?;
+            }
+            Ok(())
+        })
     }
 }
 
 pub struct Doc {
     opset: opset::Opset<DocOp, json::Tree<Id>>,
+    /// Id of this replica.
+    site: SiteId,
+    /// Highest timestamp counter this replica has generated or observed.
+    clock: u64,
+    /// `num` of the last `Id` allocated by this replica.
+    id_counter: u64,
+}
+
+impl Default for Doc {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Doc {
+    /// Creates a new, empty document with a randomly generated `SiteId`.
     pub fn new() -> Doc {
+        Self::new_with_site(SiteId::new_random())
+    }
+
+    /// Creates a new, empty document for the replica `site`. Each replica of a document must
+    /// have a distinct `SiteId`.
+    pub fn new_with_site(site: SiteId) -> Doc {
         Doc {
             opset: opset::Opset::new(json::Tree::new_with_object_root(ROOT_ID), CACHE_GAP),
+            site,
+            clock: 0,
+            id_counter: 0,
+        }
+    }
+
+    pub fn site(&self) -> SiteId {
+        self.site
+    }
+
+    /// Returns a timestamp that is greater than the timestamp of every op this replica has
+    /// created or received so far. Use this as the timestamp of the next local `DocOp`. Panics if
+    /// the clock has run out of counters; `transact` returns `TransactError::ClockOverflow`
+    /// instead.
+    pub fn next_timestamp(&mut self) -> Timestamp {
+        self.try_next_timestamp()
+            .expect("timestamp counter overflowed")
+    }
+
+    /// Like `next_timestamp`, but returns `None` instead of panicking. Counters stop short of
+    /// `u64::MAX`, since other replicas refuse ops with that counter.
+    pub(crate) fn try_next_timestamp(&mut self) -> Option<Timestamp> {
+        let counter = self.clock.checked_add(1).filter(|c| *c < u64::MAX)?;
+        self.clock = counter;
+        Some(Timestamp {
+            counter,
+            site: self.site,
+        })
+    }
+
+    /// Allocates a fresh `Id`, unique across all replicas. Every new character, array entry and
+    /// collection created by this replica needs its own id. Ids with this replica's site that
+    /// arrive through `update` are never handed out again, so a replica can be restored by
+    /// replaying its own ops into a new `Doc` with the same site.
+    pub fn next_id(&mut self) -> Id {
+        self.next_id_run(1)
+    }
+
+    /// Allocates `len` fresh `Id`s with consecutive `num`s, returning the first one. The rest are
+    /// its successors, as used by `json::Edit::TextInsertRun`. Panics if this replica has run out
+    /// of ids; transactions fail with `TreeError::IdOverflow` instead.
+    pub fn next_id_run(&mut self, len: usize) -> Id {
+        self.try_next_id_run(len).expect("id counter overflowed")
+    }
+
+    /// Like `next_id_run`, but returns `None` instead of panicking. Ids stop short of `u64::MAX`,
+    /// since other replicas refuse ops that use that id.
+    pub(crate) fn try_next_id_run(&mut self, len: usize) -> Option<Id> {
+        let first = self.id_counter.checked_add(1)?;
+        let last = self
+            .id_counter
+            .checked_add(len.max(1) as u64)
+            .filter(|last| *last < u64::MAX)?;
+        self.id_counter = last;
+        Some(Id {
+            site: self.site,
+            num: first,
+        })
+    }
+
+    /// Advances this replica's clock past `timestamp`. Called automatically for every op passed
+    /// to `update`.
+    pub fn observe(&mut self, timestamp: Timestamp) {
+        self.clock = self.clock.max(timestamp.counter);
+    }
+
+    /// Applies `op` to the document. Delivering the same op more than once is harmless.
+    pub fn update(&mut self, op: DocOp) -> UpdateReport {
+        self.update_from_iter(std::iter::once(op))
+    }
+
+    /// Applies many ops at once. This is faster than calling `update` for each op.
+    pub fn update_from_iter<I: std::iter::Iterator<Item = DocOp>>(
+        &mut self,
+        iter: I,
+    ) -> UpdateReport {
+        let site = self.site;
+        let mut timestamps = Vec::new();
+        let mut rejected = Vec::new();
+        let mut id_counter = self.id_counter;
+        let ops = iter.filter(|op| {
+            let last_num = match last_num_minted_by(op, site) {
+                Some(num) if num < u64::MAX && op.timestamp.counter < u64::MAX => num,
+                _ => {
+                    rejected.push(op.timestamp);
+                    return false;
+                }
+            };
+            timestamps.push(op.timestamp);
+            id_counter = id_counter.max(last_num);
+            true
+        });
+        let results = self.opset.update_from_iter(ops);
+        self.id_counter = id_counter;
+        let mut report = UpdateReport {
+            rejected,
+            ..UpdateReport::default()
+        };
+        for (timestamp, inserted) in timestamps.into_iter().zip(results.inserted) {
+            self.observe(timestamp);
+            match inserted {
+                Ok(true) => report.applied.push(timestamp),
+                Ok(false) => report.duplicates.push(timestamp),
+                Err(opset::OpsetError::ConflictingOp) => report.conflicts.push(timestamp),
+            }
         }
+        report.failures = results
+            .failures
+            .into_iter()
+            .map(|(op, error)| (op.timestamp, error))
+            .collect();
+        report
+    }
+
+    /// Returns every op in this document that failed to apply, in timestamp order. Failed ops
+    /// have no effect on the document.
+    pub fn failures(&self) -> impl Iterator<Item = (&DocOp, &DocOpError)> {
+        self.opset.failures()
+    }
+
+    /// If `op` has been applied to this document and failed, returns why.
+    pub fn failure(&self, op: &DocOp) -> Option<&DocOpError> {
+        self.opset.failure(op)
     }
 
-    pub fn update(&mut self, op: DocOp) {
-        self.opset.update(op);
+    /// Materializes the whole document as plain JSON. This is lossy: values embedded in strings
+    /// are left out, along with anything else plain JSON can't hold. See `Tree::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let tree = self.tree();
+        tree.to_json(&tree.root_value())
+            .expect("root of document was not in tree")
     }
 
-    pub fn update_from_iter<I: std::iter::Iterator<Item = DocOp>>(&mut self, iter: I) {
-        self.opset.update_from_iter(iter);
+    /// Materializes the whole document as a JSON string. See `Tree::to_json`.
+    pub fn to_json_string(&self) -> String {
+        self.to_json().to_string()
     }
 
     pub fn tree(&self) -> &json::Tree<Id> {
         self.opset.state()
     }
 }
+
+/// Returns the largest `num` of the ids with site `site` that `op` refers to, counting every id in
+/// a run, or 0 if there are none. Returns `None` if a run of those ids goes past `u64::MAX`.
+fn last_num_minted_by(op: &DocOp, site: SiteId) -> Option<u64> {
+    let mut last = 0;
+    for edit in &op.edits {
+        for id in edit.ids() {
+            if id.site == site {
+                last = last.max(id.num);
+            }
+        }
+        let runs = match edit {
+            json::Edit::TextInsertRun { id, text, .. } => {
+                vec![(&id.0, text.chars().count())]
+            }
+            json::Edit::TextDeleteRange { ids } | json::Edit::ArrayDeleteRange { ids } => {
+                ids.iter().map(|run| (&run.start, run.len)).collect()
+            }
+            _ => Vec::new(),
+        };
+        for (start, len) in runs {
+            if start.site == site {
+                let len = len.saturating_sub(1) as u64;
+                last = last.max(start.num.checked_add(len)?);
+            }
+        }
+    }
+    Some(last)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(n: u8) -> SiteId {
+        SiteId([n; 16])
+    }
+
+    #[test]
+    fn timestamps_break_ties_by_site() {
+        let a = Timestamp {
+            counter: 1,
+            site: site(1),
+        };
+        let b = Timestamp {
+            counter: 1,
+            site: site(2),
+        };
+        let c = Timestamp {
+            counter: 2,
+            site: site(1),
+        };
+        assert!(a < b);
+        assert!(b < c);
+    }
+
+    #[test]
+    fn next_timestamp_follows_observed_ops() {
+        let mut doc_a = Doc::new_with_site(site(1));
+        let mut doc_b = Doc::new_with_site(site(2));
+
+        let op_a = DocOp {
+            timestamp: doc_a.next_timestamp(),
+            edits: vec![],
+        };
+        let op_b = DocOp {
+            timestamp: doc_b.next_timestamp(),
+            edits: vec![],
+        };
+        // concurrent ops with the same counter don't collide
+        assert_eq!(op_a.timestamp.counter, op_b.timestamp.counter);
+        assert_ne!(op_a, op_b);
+
+        doc_a.update(op_a.clone());
+        doc_a.update(op_b.clone());
+        doc_b.update_from_iter(vec![op_b, op_a].into_iter());
+
+        assert_eq!(doc_a.next_timestamp().counter, 2);
+        assert_eq!(doc_b.next_timestamp().counter, 2);
+    }
+
+    #[test]
+    fn clock_never_overflows() {
+        let mut doc = Doc::new_with_site(site(1));
+        let op = |counter| DocOp {
+            timestamp: Timestamp {
+                counter,
+                site: site(2),
+            },
+            edits: vec![],
+        };
+        let report = doc.update(op(u64::MAX));
+        assert_eq!(report.rejected, vec![op(u64::MAX).timestamp]);
+        assert!(report.applied.is_empty());
+        assert_eq!(doc.next_timestamp().counter, 1);
+
+        // the last counter below `u64::MAX` is accepted, but leaves no room for local ops
+        let report = doc.update(op(u64::MAX - 1));
+        assert_eq!(report.applied, vec![op(u64::MAX - 1).timestamp]);
+        assert_eq!(
+            doc.transact(|tx| tx.set(&json::ObjectRef(ROOT_ID), "key", json::Value::Null)),
+            Err(crate::TransactError::ClockOverflow)
+        );
+        assert_eq!(doc.to_json(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn concurrent_replicas_allocate_distinct_ids() {
+        let mut doc_a = Doc::new_with_site(site(1));
+        let mut doc_b = Doc::new_with_site(site(2));
+
+        let mut ops = vec![];
+        for doc in [&mut doc_a, &mut doc_b].iter_mut() {
+            let id = doc.next_id();
+            ops.push(DocOp {
+                timestamp: doc.next_timestamp(),
+                edits: vec![
+                    json::Edit::MapCreate {
+                        id: json::ObjectRef(id.clone()),
+                    },
+                    json::Edit::MapInsert {
+                        parent: json::ObjectRef(ROOT_ID),
+                        key: format!("{:?}", doc.site()),
+                        item: json::Value::Object(json::ObjectRef(id)),
+                    },
+                ],
+            });
+        }
+        assert_ne!(ops[0].edits[0], ops[1].edits[0]);
+
+        doc_a.update_from_iter(ops.clone().into_iter());
+        doc_b.update_from_iter(ops.into_iter().rev());
+        for doc in &[&doc_a, &doc_b] {
+            for site in &[site(1), site(2)] {
+                let value = json::ObjectRef(ROOT_ID).get(doc.tree(), &format!("{:?}", site));
+                assert!(matches!(value, Ok(json::Value::Object(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn restored_replica_does_not_reuse_ids() {
+        let mut doc = Doc::new_with_site(site(1));
+        let op = doc
+            .transact(|tx| {
+                let string = tx.create_string()?;
+                tx.set(
+                    &json::ObjectRef(ROOT_ID),
+                    "text",
+                    json::Value::String(string.clone()),
+                )?;
+                tx.insert_text(&string, 0, "hello")
+            })
+            .unwrap();
+
+        // replaying this site's own ops moves the id counter past every id in them, including
+        // the ones inside the text run
+        let mut restored = Doc::new_with_site(site(1));
+        restored.update(op);
+        assert_eq!(restored.next_id(), doc.next_id());
+        let op = restored
+            .transact(|tx| {
+                let string = tx.create_string()?;
+                tx.set(
+                    &json::ObjectRef(ROOT_ID),
+                    "other",
+                    json::Value::String(string),
+                )
+            })
+            .unwrap();
+        assert_eq!(restored.failure(&op), None);
+    }
+
+    #[test]
+    fn id_counter_never_overflows() {
+        let mut doc = Doc::new_with_site(site(1));
+        let mut other = Doc::new_with_site(site(2));
+        let mut claim = |num| DocOp {
+            timestamp: other.next_timestamp(),
+            edits: vec![json::Edit::MapCreate {
+                id: json::ObjectRef(Id { site: site(1), num }),
+            }],
+        };
+        let op = claim(u64::MAX);
+        let report = doc.update(op.clone());
+        assert_eq!(report.rejected, vec![op.timestamp]);
+        assert_eq!(doc.next_id().num, 1);
+
+        // the last id below `u64::MAX` is accepted, but leaves no room for local ids
+        let op = claim(u64::MAX - 1);
+        assert_eq!(doc.update(op.clone()).applied, vec![op.timestamp]);
+        assert_eq!(
+            doc.transact(|tx| tx.create_object().map(|_| ())),
+            Err(crate::TransactError::Tree(json::TreeError::IdOverflow))
+        );
+    }
+
+    #[test]
+    fn failed_ops_are_rolled_back() {
+        let mut doc = Doc::new_with_site(site(1));
+        let id = doc.next_id();
+        let op = DocOp {
+            timestamp: doc.next_timestamp(),
+            edits: vec![
+                json::Edit::MapCreate {
+                    id: json::ObjectRef(id.clone()),
+                },
+                json::Edit::MapInsert {
+                    parent: json::ObjectRef(ROOT_ID),
+                    key: "key".to_string(),
+                    item: json::Value::Object(json::ObjectRef(id.clone())),
+                },
+                json::Edit::TextDelete {
+                    id: json::StringIndex(doc.next_id()),
+                },
+            ],
+        };
+        let error = DocOpError {
+            edit_index: 2,
+            error: json::TreeError::UnknownId,
+        };
+        let report = doc.update(op.clone());
+        assert_eq!(report.applied, vec![op.timestamp]);
+        assert_eq!(report.failures, vec![(op.timestamp, error.clone())]);
+        assert_eq!(doc.failure(&op), Some(&error));
+        assert_eq!(
+            json::ObjectRef(ROOT_ID).get(doc.tree(), "key"),
+            Ok(json::Value::Unset)
+        );
+        assert_eq!(
+            json::ObjectRef(id).parent(doc.tree()),
+            Err(json::TreeError::UnknownId)
+        );
+    }
+
+    #[test]
+    fn update_reports_failures() {
+        let mut doc_a = Doc::new_with_site(site(1));
+        let mut doc_b = Doc::new_with_site(site(0));
+        let object = doc_a.next_id();
+        let create = DocOp {
+            timestamp: doc_a.next_timestamp(),
+            edits: vec![json::Edit::MapCreate {
+                id: json::ObjectRef(object.clone()),
+            }],
+        };
+        let insert = DocOp {
+            timestamp: doc_a.next_timestamp(),
+            edits: vec![json::Edit::MapInsert {
+                parent: json::ObjectRef(ROOT_ID),
+                key: "key".to_string(),
+                item: json::Value::Object(json::ObjectRef(object.clone())),
+            }],
+        };
+        // a misbehaving replica reuses the same id, and manages to get an earlier timestamp
+        let reuse = DocOp {
+            timestamp: doc_b.next_timestamp(),
+            edits: vec![json::Edit::ArrayCreate {
+                id: json::ArrayRef(object.clone()),
+            }],
+        };
+
+        let report = doc_a.update_from_iter(vec![create.clone(), insert.clone()].into_iter());
+        assert_eq!(report.applied, vec![create.timestamp, insert.timestamp]);
+        assert!(report.failures.is_empty());
+
+        let report = doc_a.update(create.clone());
+        assert_eq!(report.duplicates, vec![create.timestamp]);
+
+        let mut conflicting = create.clone();
+        conflicting.edits.clear();
+        let report = doc_a.update(conflicting);
+        assert_eq!(report.conflicts, vec![create.timestamp]);
+
+        let report = doc_a.update(reuse.clone());
+        assert_eq!(report.applied, vec![reuse.timestamp]);
+        let duplicate_id = DocOpError {
+            edit_index: 0,
+            error: json::TreeError::DuplicateId,
+        };
+        assert_eq!(
+            report.failures,
+            vec![(create.timestamp, duplicate_id.clone())]
+        );
+        assert_eq!(
+            doc_a
+                .failures()
+                .map(|(op, e)| (op.timestamp, e.clone()))
+                .collect::<Vec<_>>(),
+            vec![(create.timestamp, duplicate_id)]
+        );
+    }
+
+    #[test]
+    fn ops_roundtrip_through_serde() {
+        let mut doc = Doc::new_with_site(site(1));
+        let ids: Vec<Id> = (0..4).map(|_| doc.next_id()).collect();
+        let op = DocOp {
+            timestamp: doc.next_timestamp(),
+            edits: vec![
+                json::Edit::MapCreate {
+                    id: json::ObjectRef(ids[0].clone()),
+                },
+                json::Edit::ArrayCreate {
+                    id: json::ArrayRef(ids[1].clone()),
+                },
+                json::Edit::TextCreate {
+                    id: json::StringRef(ids[2].clone()),
+                },
+                json::Edit::MapInsert {
+                    parent: json::ObjectRef(ROOT_ID),
+                    key: "key".to_string(),
+                    item: json::Value::Object(json::ObjectRef(ids[0].clone())),
+                },
+                json::Edit::ArrayInsert {
+                    index: json::ArrayIndex(ids[1].clone()),
+                    id: json::ArrayIndex(ids[3].clone()),
+                    item: json::Value::String(json::StringRef(ids[2].clone())),
+                },
+                json::Edit::ArrayDelete {
+                    id: json::ArrayIndex(ids[3].clone()),
+                },
+                json::Edit::TextInsert {
+                    index: json::StringIndex(ids[2].clone()),
+                    id: json::StringIndex(doc.next_id()),
+                    character: '🎉',
+                },
+                json::Edit::TextDelete {
+                    id: json::StringIndex(ids[2].clone()),
+                },
+            ],
+        };
+        let values = vec![
+            json::Value::Array(json::ArrayRef(ids[1].clone())),
+            json::Value::Int(-5),
+            json::Value::True,
+            json::Value::False,
+            json::Value::Null,
+            json::Value::Unset,
+        ];
+
+        let serialized = serde_json::to_string(&op).unwrap();
+        assert_eq!(serde_json::from_str::<DocOp>(&serialized).unwrap(), op);
+        let serialized = serde_json::to_string(&values).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<json::Value<Id>>>(&serialized).unwrap(),
+            values
+        );
+    }
+
+    #[test]
+    fn floats_round_trip_through_serde() {
+        let floats = [
+            f64::NAN,
+            -f64::NAN,
+            0.0,
+            -0.0,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            1.5,
+        ];
+        let values: Vec<json::Value<Id>> = floats.iter().map(|f| json::Value::Float(*f)).collect();
+        let serialized = serde_json::to_string(&values).unwrap();
+        let deserialized: Vec<json::Value<Id>> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.len(), floats.len());
+        for (value, float) in deserialized.iter().zip(&floats) {
+            match value {
+                json::Value::Float(f) => assert_eq!(f.to_bits(), float.to_bits()),
+                other => panic!("expected a float, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn serde_representation_is_stable() {
+        let op = DocOp {
+            timestamp: Timestamp {
+                counter: 3,
+                site: site(1),
+            },
+            edits: vec![json::Edit::MapInsert {
+                parent: json::ObjectRef(ROOT_ID),
+                key: "k".to_string(),
+                item: json::Value::Int(1),
+            }],
+        };
+        let site_json = "[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]";
+        let root_json = "{\"site\":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],\"num\":0}";
+        assert_eq!(
+            serde_json::to_string(&op).unwrap(),
+            format!(
+                "{{\"timestamp\":{{\"counter\":3,\"site\":{}}},\"edits\":[{{\"MapInsert\":{{\"parent\":{},\"key\":\"k\",\"item\":{{\"Int\":1}}}}}}]}}",
+                site_json, root_json
+            )
+        );
+    }
+}
@@ -1,8 +1,18 @@
+mod counted;
+mod export;
+mod marks;
 mod sequence;
 #[cfg(test)]
 mod test;
+mod text;
 mod tree;
 mod value;
 
-pub use tree::{Edit, Tree, TreeError};
-pub use value::{ArrayIndex, ArrayRef, ObjectRef, StringIndex, StringRef, Value};
+pub use export::BytesEncoding;
+pub use marks::{Expand, Span};
+pub use text::{LineColumn, TextUnit};
+pub use tree::{Edit, IdRange, SequentialId, Tree, TreeError};
+pub use value::{
+    ArrayIndex, ArrayIter, ArrayRef, CounterRef, ObjectRef, Parent, SetRef, StringIndex,
+    StringIter, StringRef, TextChunk, TextChunks, Value, EMBED_CHAR,
+};
@@ -0,0 +1,456 @@
+use super::text::TextUnit;
+use super::tree::NodeId;
+use im::HashMap;
+use std::ops::{Add, Sub};
+use std::sync::Arc;
+
+/// The size of some run of live items in a sequence, in each unit positions can be measured in.
+/// Arrays only count `items`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct Weight {
+    /// Number of live items; characters for strings, entries for arrays.
+    pub(super) items: usize,
+    pub(super) utf8: usize,
+    pub(super) utf16: usize,
+}
+
+impl Weight {
+    pub(super) fn of_char(character: char) -> Self {
+        Weight {
+            items: 1,
+            utf8: character.len_utf8(),
+            utf16: character.len_utf16(),
+        }
+    }
+
+    pub(super) fn of_str(string: &str) -> Self {
+        string
+            .chars()
+            .map(Weight::of_char)
+            .fold(Weight::default(), Add::add)
+    }
+
+    pub(super) fn item() -> Self {
+        Weight {
+            items: 1,
+            utf8: 0,
+            utf16: 0,
+        }
+    }
+
+    pub(super) fn get(&self, unit: TextUnit) -> usize {
+        match unit {
+            TextUnit::Utf8 => self.utf8,
+            TextUnit::Utf16 => self.utf16,
+            TextUnit::Scalar => self.items,
+        }
+    }
+}
+
+impl Add for Weight {
+    type Output = Weight;
+
+    fn add(self, other: Weight) -> Weight {
+        Weight {
+            items: self.items + other.items,
+            utf8: self.utf8 + other.utf8,
+            utf16: self.utf16 + other.utf16,
+        }
+    }
+}
+
+impl Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, other: Weight) -> Weight {
+        Weight {
+            items: self.items - other.items,
+            utf8: self.utf8 - other.utf8,
+            utf16: self.utf16 - other.utf16,
+        }
+    }
+}
+
+/// An order-statistic tree over the segments of a single sequence, stored on the sequence's
+/// container node. Each segment is given an order label when it's inserted, and the segments are
+/// kept in a persistent treap sorted by label, where every entry knows the total weight of its
+/// subtree. This lets us find the segment containing a position, or the position of a segment, in
+/// `O(log n)` in the number of segments, while sharing structure between clones of the tree.
+#[derive(Clone, Debug)]
+pub(super) struct SegmentIndex {
+    /// Order label of every segment in the sequence.
+    labels: HashMap<NodeId, u64>,
+    root: Link,
+}
+
+type Link = Option<Arc<Entry>>;
+
+#[derive(Clone, Debug)]
+struct Entry {
+    label: u64,
+    segment: NodeId,
+    weight: Weight,
+    /// Sum of the weights of every entry in this subtree.
+    total: Weight,
+    /// Number of entries in this subtree.
+    count: usize,
+    left: Link,
+    right: Link,
+}
+
+impl SegmentIndex {
+    /// Creates an index for a sequence with one empty segment.
+    pub(super) fn new(segment: NodeId) -> Self {
+        let mut index = SegmentIndex {
+            labels: HashMap::new(),
+            root: None,
+        };
+        index.insert(u64::MAX / 2, segment, Weight::default());
+        index
+    }
+
+    /// Returns the total weight of every segment in the sequence.
+    pub(super) fn total(&self) -> Weight {
+        total(&self.root)
+    }
+
+    pub(super) fn weight(&self, segment: NodeId) -> Weight {
+        let label = self.label(segment);
+        let mut link = &self.root;
+        while let Some(entry) = link {
+            if label < entry.label {
+                link = &entry.left;
+            } else if label > entry.label {
+                link = &entry.right;
+            } else {
+                return entry.weight;
+            }
+        }
+        panic!("segment label was not in segment index");
+    }
+
+    pub(super) fn set_weight(&mut self, segment: NodeId, weight: Weight) {
+        let label = self.label(segment);
+        let root = self.root.as_ref().expect("segment index was empty");
+        self.root = Some(set_weight(root, label, weight));
+    }
+
+    /// Returns the total weight of the segments before `segment`.
+    pub(super) fn weight_before(&self, segment: NodeId) -> Weight {
+        let label = self.label(segment);
+        let mut before = Weight::default();
+        let mut link = &self.root;
+        while let Some(entry) = link {
+            if label < entry.label {
+                link = &entry.left;
+            } else if label > entry.label {
+                before = before + total(&entry.left) + entry.weight;
+                link = &entry.right;
+            } else {
+                return before + total(&entry.left);
+            }
+        }
+        panic!("segment label was not in segment index");
+    }
+
+    /// Returns true if `a` comes before `b` in the sequence.
+    pub(super) fn is_before(&self, a: NodeId, b: NodeId) -> bool {
+        self.label(a) < self.label(b)
+    }
+
+    /// Finds the segment containing the unit at position `offset`, returning it along with the
+    /// total weight of the segments before it. Returns `None` if `offset` is past the end of the
+    /// sequence.
+    pub(super) fn find(&self, mut offset: usize, unit: TextUnit) -> Option<(NodeId, Weight)> {
+        let mut before = Weight::default();
+        let mut link = &self.root;
+        while let Some(entry) = link {
+            let left = total(&entry.left);
+            if offset < left.get(unit) {
+                link = &entry.left;
+                continue;
+            }
+            offset -= left.get(unit);
+            before = before + left;
+            if offset < entry.weight.get(unit) {
+                return Some((entry.segment, before));
+            }
+            offset -= entry.weight.get(unit);
+            before = before + entry.weight;
+            link = &entry.right;
+        }
+        None
+    }
+
+    /// Adds `segment` to the index, directly after `prev` and before `next`. `next` is `None` if
+    /// `prev` is the last segment in the sequence.
+    pub(super) fn insert_after(
+        &mut self,
+        prev: NodeId,
+        next: Option<NodeId>,
+        segment: NodeId,
+        weight: Weight,
+    ) {
+        let low = self.label(prev);
+        let high = next.map(|next| self.label(next)).unwrap_or(u64::MAX);
+        if high - low < 2 {
+            // ran out of room between the labels; spread out the labels near `prev` and retry
+            self.relabel_around(low);
+            return self.insert_after(prev, next, segment, weight);
+        }
+        self.insert(low + (high - low) / 2, segment, weight);
+    }
+
+    fn label(&self, segment: NodeId) -> u64 {
+        *self
+            .labels
+            .get(&segment)
+            .expect("segment was not in segment index")
+    }
+
+    fn insert(&mut self, label: u64, segment: NodeId, weight: Weight) {
+        self.labels.insert(segment, label);
+        self.root = Some(insert(&self.root, label, segment, weight));
+    }
+
+    /// Evenly spaces out the labels in the smallest aligned range of labels around `label` that
+    /// isn't too crowded. The allowed crowding shrinks as the range grows, so big ranges are
+    /// rarely relabeled, and inserting a segment takes `O(log n)` amortized time even if every
+    /// insert is at the same place. This is the order-maintenance scheme from Bender et al.,
+    /// "Two Simplified Algorithms for Maintaining Order in a List".
+    fn relabel_around(&mut self, label: u64) {
+        for bits in 1..=64 {
+            let size = 1u128 << bits;
+            let low = (u128::from(label) & !(size - 1)) as u64;
+            let high = (u128::from(low) + size - 1) as u64;
+            let count = count_at_most(&self.root, high) - count_below(&self.root, low);
+            let slots = count as u128 + 1;
+            // a range with `2^bits` labels may hold up to `2^(bits / 2)` segments
+            if slots * slots > size && bits < 64 {
+                continue;
+            }
+            let gap = size / slots;
+            let mut labels = (1..=count as u128).map(|i| (u128::from(low) + gap * i) as u64);
+            self.root = relabel(&self.root, low, high, &mut labels, &mut self.labels);
+            return;
+        }
+    }
+}
+
+fn total(link: &Link) -> Weight {
+    link.as_ref().map(|entry| entry.total).unwrap_or_default()
+}
+
+/// Treap priority of an entry. This is a hash of the entry's segment rather than its label, so
+/// relabeling never changes the shape of the tree.
+fn priority(segment: NodeId) -> u64 {
+    // splitmix64
+    let mut z = (segment.0 as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn make(entry: &Entry, weight: Weight, left: Link, right: Link) -> Arc<Entry> {
+    Arc::new(Entry {
+        label: entry.label,
+        segment: entry.segment,
+        weight,
+        total: total(&left) + weight + total(&right),
+        count: count(&left) + 1 + count(&right),
+        left,
+        right,
+    })
+}
+
+fn insert(link: &Link, label: u64, segment: NodeId, weight: Weight) -> Arc<Entry> {
+    let entry = match link {
+        Some(entry) => entry,
+        None => {
+            return Arc::new(Entry {
+                label,
+                segment,
+                weight,
+                total: weight,
+                count: 1,
+                left: None,
+                right: None,
+            })
+        }
+    };
+    if label < entry.label {
+        let left = insert(&entry.left, label, segment, weight);
+        if priority(left.segment) > priority(entry.segment) {
+            // rotate right
+            let right = make(entry, entry.weight, left.right.clone(), entry.right.clone());
+            make(&left, left.weight, left.left.clone(), Some(right))
+        } else {
+            make(entry, entry.weight, Some(left), entry.right.clone())
+        }
+    } else {
+        let right = insert(&entry.right, label, segment, weight);
+        if priority(right.segment) > priority(entry.segment) {
+            // rotate left
+            let left = make(entry, entry.weight, entry.left.clone(), right.left.clone());
+            make(&right, right.weight, Some(left), right.right.clone())
+        } else {
+            make(entry, entry.weight, entry.left.clone(), Some(right))
+        }
+    }
+}
+
+fn set_weight(entry: &Arc<Entry>, label: u64, weight: Weight) -> Arc<Entry> {
+    if label < entry.label {
+        let left = set_weight(entry.left.as_ref().expect("label not found"), label, weight);
+        make(entry, entry.weight, Some(left), entry.right.clone())
+    } else if label > entry.label {
+        let right = set_weight(
+            entry.right.as_ref().expect("label not found"),
+            label,
+            weight,
+        );
+        make(entry, entry.weight, entry.left.clone(), Some(right))
+    } else {
+        make(entry, weight, entry.left.clone(), entry.right.clone())
+    }
+}
+
+fn count(link: &Link) -> usize {
+    link.as_ref().map(|entry| entry.count).unwrap_or(0)
+}
+
+/// Returns the number of entries with a label less than `label`.
+fn count_below(mut link: &Link, label: u64) -> usize {
+    let mut below = 0;
+    while let Some(entry) = link {
+        if entry.label < label {
+            below += count(&entry.left) + 1;
+            link = &entry.right;
+        } else {
+            link = &entry.left;
+        }
+    }
+    below
+}
+
+/// Returns the number of entries with a label less than or equal to `label`.
+fn count_at_most(mut link: &Link, label: u64) -> usize {
+    let mut at_most = 0;
+    while let Some(entry) = link {
+        if entry.label <= label {
+            at_most += count(&entry.left) + 1;
+            link = &entry.right;
+        } else {
+            link = &entry.left;
+        }
+    }
+    at_most
+}
+
+/// Gives the entries with labels from `low` to `high` inclusive the next labels from `labels`, in
+/// order, recording them in `index_labels`. The new labels must keep the entries in the same order
+/// relative to every other entry. Only the entries in the range and their ancestors are copied.
+fn relabel(
+    link: &Link,
+    low: u64,
+    high: u64,
+    labels: &mut impl Iterator<Item = u64>,
+    index_labels: &mut HashMap<NodeId, u64>,
+) -> Link {
+    let entry = link.as_ref()?;
+    let left = if entry.label > low {
+        relabel(&entry.left, low, high, labels, index_labels)
+    } else {
+        entry.left.clone()
+    };
+    let label = if low <= entry.label && entry.label <= high {
+        let label = labels.next().expect("ran out of labels while relabeling");
+        index_labels.insert(entry.segment, label);
+        label
+    } else {
+        entry.label
+    };
+    let right = if entry.label < high {
+        relabel(&entry.right, low, high, labels, index_labels)
+    } else {
+        entry.right.clone()
+    };
+    Some(Arc::new(Entry {
+        label,
+        segment: entry.segment,
+        weight: entry.weight,
+        total: entry.total,
+        count: entry.count,
+        left,
+        right,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn items(n: usize) -> Weight {
+        Weight {
+            items: n,
+            ..Weight::default()
+        }
+    }
+
+    #[test]
+    fn finds_segments_by_position() {
+        let mut index = SegmentIndex::new(NodeId(0));
+        index.set_weight(NodeId(0), items(3));
+        index.insert_after(NodeId(0), None, NodeId(1), items(0));
+        index.insert_after(NodeId(1), None, NodeId(2), items(5));
+        index.insert_after(NodeId(0), Some(NodeId(1)), NodeId(3), items(2));
+        // order is 0, 3, 1, 2
+        assert_eq!(index.total(), items(10));
+        assert_eq!(index.weight_before(NodeId(3)), items(3));
+        assert_eq!(index.weight_before(NodeId(1)), items(5));
+        assert_eq!(index.weight_before(NodeId(2)), items(5));
+        assert_eq!(index.find(2, TextUnit::Scalar), Some((NodeId(0), items(0))));
+        assert_eq!(index.find(3, TextUnit::Scalar), Some((NodeId(3), items(3))));
+        // empty segments are skipped
+        assert_eq!(index.find(5, TextUnit::Scalar), Some((NodeId(2), items(5))));
+        assert_eq!(index.find(10, TextUnit::Scalar), None);
+        assert!(index.is_before(NodeId(3), NodeId(1)));
+        assert!(!index.is_before(NodeId(2), NodeId(3)));
+    }
+
+    #[test]
+    fn relabels_when_labels_run_out() {
+        let mut index = SegmentIndex::new(NodeId(0));
+        index.set_weight(NodeId(0), items(1));
+        index.insert_after(NodeId(0), None, NodeId(1), items(1));
+        // always inserting directly after segment 0 halves the gap every time
+        for i in 2..200 {
+            index.insert_after(NodeId(0), Some(NodeId(i - 1)), NodeId(i), items(1));
+        }
+        assert_eq!(index.total(), items(200));
+        assert_eq!(index.weight_before(NodeId(199)), items(1));
+        assert_eq!(index.weight_before(NodeId(2)), items(198));
+        assert_eq!(index.weight_before(NodeId(1)), items(199));
+        let snapshot = index.clone();
+        index.set_weight(NodeId(199), items(0));
+        assert_eq!(index.weight_before(NodeId(1)), items(198));
+        assert_eq!(snapshot.weight_before(NodeId(1)), items(199));
+    }
+
+    #[test]
+    fn relabels_only_nearby_segments() {
+        let mut index = SegmentIndex::new(NodeId(0));
+        index.set_weight(NodeId(0), items(1));
+        let first_label = index.label(NodeId(0));
+        // appending at the end halves the gap to the end of the label space every time
+        for i in 1..10_000 {
+            index.insert_after(NodeId(i - 1), None, NodeId(i), items(1));
+        }
+        assert_eq!(index.label(NodeId(0)), first_label);
+        for i in 1..10_000 {
+            assert!(index.is_before(NodeId(i - 1), NodeId(i)));
+            assert_eq!(index.weight_before(NodeId(i)), items(i));
+        }
+        assert_eq!(index.total(), items(10_000));
+    }
+}
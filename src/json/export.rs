@@ -0,0 +1,115 @@
+use super::tree::{NodeType, SequentialId, Tree, TreeError};
+use super::value::{self, Value};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// How `Tree::to_json_with` writes `Value::Bytes`, since JSON has no type for binary data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BytesEncoding {
+    /// A string of standard base64, with padding. This is what `Tree::to_json` uses.
+    Base64,
+    /// A string of lowercase hex digits, two per byte.
+    Hex,
+    /// An array of numbers from 0 to 255.
+    Array,
+}
+
+impl BytesEncoding {
+    fn encode(self, bytes: &[u8]) -> serde_json::Value {
+        match self {
+            BytesEncoding::Base64 => serde_json::Value::String(base64(bytes)),
+            BytesEncoding::Hex => serde_json::Value::String(
+                bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            ),
+            BytesEncoding::Array => serde_json::Value::Array(
+                bytes
+                    .iter()
+                    .map(|byte| serde_json::Value::from(*byte))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (u32::from(*byte) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((bits >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> Tree<Id> {
+    /// Returns the root value of the tree.
+    pub fn root_value(&self) -> Value<Id> {
+        let root = self.root().clone();
+        match self.get_type(root.clone()) {
+            Ok(NodeType::Object) => Value::Object(value::ObjectRef(root)),
+            Ok(NodeType::Array) => Value::Array(value::ArrayRef(root)),
+            Ok(NodeType::String) => Value::String(value::StringRef(root)),
+            e => panic!("root had unexpected type: {:?}", e),
+        }
+    }
+
+    /// Recursively materializes `value` as plain JSON. Object keys are sorted, so the output is
+    /// the same on every replica. `Value::Unset` becomes `null`, as do floats that JSON can't
+    /// represent, like NaN and infinity. This is lossy for strings with embeds: the embedded
+    /// values are left out, and only their `EMBED_CHAR` placeholders remain, so read them with
+    /// `StringRef::chunks` instead. Bytes are written as base64; see `to_json_with` for other
+    /// encodings. Takes `O(n)` in the size of `value`.
+    pub fn to_json(&self, value: &Value<Id>) -> Result<serde_json::Value, TreeError> {
+        self.to_json_with(value, BytesEncoding::Base64)
+    }
+
+    /// Like `to_json`, but writes `Value::Bytes` with `bytes_encoding`.
+    pub fn to_json_with(
+        &self,
+        value: &Value<Id>,
+        bytes_encoding: BytesEncoding,
+    ) -> Result<serde_json::Value, TreeError> {
+        Ok(match value {
+            Value::Null | Value::Unset => serde_json::Value::Null,
+            Value::True => serde_json::Value::Bool(true),
+            Value::False => serde_json::Value::Bool(false),
+            Value::Int(i) => serde_json::Value::from(*i),
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::Bytes(bytes) => bytes_encoding.encode(bytes),
+            Value::Counter(counter) => serde_json::Value::from(counter.value(self)?),
+            Value::Set(set) => serde_json::Value::Array(
+                set.iter(self)?
+                    .map(|item| self.to_json_with(&item, bytes_encoding))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::String(string) => serde_json::Value::String(string.to_string(self)?),
+            Value::Array(array) => serde_json::Value::Array(
+                array
+                    .to_vec(self)?
+                    .iter()
+                    .map(|item| self.to_json_with(item, bytes_encoding))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(object) => {
+                let mut map = serde_json::Map::new();
+                for (key, item) in object.entries(self)? {
+                    map.insert(key, self.to_json_with(&item, bytes_encoding)?);
+                }
+                serde_json::Value::Object(map)
+            }
+        })
+    }
+
+    /// Like `to_json`, but serializes the result as a compact JSON string.
+    pub fn to_json_string(&self, value: &Value<Id>) -> Result<String, TreeError> {
+        Ok(self.to_json(value)?.to_string())
+    }
+}
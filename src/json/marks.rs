@@ -0,0 +1,295 @@
+use super::sequence;
+use super::tree::{NodeData, NodeType, SequentialId, Tree, TreeError};
+use super::value::StringRef;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Whether text inserted at the edges of a mark takes on its formatting. Bold usually expands
+/// after, so typing at the end of bold text stays bold, while links usually don't expand at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Expand {
+    None,
+    Before,
+    After,
+    Both,
+}
+
+impl Expand {
+    fn before(self) -> bool {
+        matches!(self, Expand::Before | Expand::Both)
+    }
+
+    fn after(self) -> bool {
+        matches!(self, Expand::After | Expand::Both)
+    }
+}
+
+/// A formatting mark on a string, as added by `Edit::TextMark`.
+#[derive(Clone, Debug)]
+pub(super) struct Mark<Id> {
+    pub(super) start: Id,
+    pub(super) end: Id,
+    pub(super) expand: Expand,
+    pub(super) name: String,
+    pub(super) value: serde_json::Value,
+}
+
+impl<Id: PartialEq> Mark<Id> {
+    /// Returns where the range starts, in the string `string`.
+    fn start_edge<'a>(&'a self, string: &Id) -> Edge<'a, Id> {
+        if &self.start == string {
+            Edge::StringStart
+        } else if self.expand.before() {
+            Edge::Char(&self.start, Side::After)
+        } else {
+            Edge::Char(&self.start, Side::On)
+        }
+    }
+
+    /// Returns where the range ends, in the string `string`.
+    fn end_edge<'a>(&'a self, string: &Id) -> Edge<'a, Id> {
+        if &self.end == string {
+            Edge::StringEnd
+        } else if self.expand.after() {
+            Edge::Char(&self.end, Side::Before)
+        } else {
+            Edge::Char(&self.end, Side::On)
+        }
+    }
+}
+
+/// An edge of a mark's range. Starts are only ever `On` or `After` a character, and ends `On` or
+/// `Before` one, so two starts, two ends, or an end and a start on different characters are never
+/// in the same gap, and inserting text can't change how they're ordered.
+enum Edge<'a, Id> {
+    StringStart,
+    /// On a character, or in the gap on one side of it.
+    Char(&'a Id, Side),
+    StringEnd,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Side {
+    Before,
+    On,
+    After,
+}
+
+/// A run of consecutive characters with the same formatting, returned by `StringRef::spans`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    /// The formatting attributes of the text, by name.
+    pub attributes: BTreeMap<String, serde_json::Value>,
+}
+
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> Tree<Id> {
+    /// Adds a mark setting the attribute `name` to `value` on a range of characters, or removing
+    /// the attribute if `value` is null. See `Edit::TextMark` for how the range is anchored.
+    pub(super) fn mark_text(
+        &mut self,
+        start: Id,
+        end: Id,
+        expand: Expand,
+        name: String,
+        value: serde_json::Value,
+    ) -> Result<(), TreeError> {
+        let string = self.anchor_string(&start)?;
+        if self.anchor_string(&end)? != string {
+            return Err(TreeError::InvalidRange);
+        }
+        if start != string && end != string && !sequence::in_order(self, &start, &end)? {
+            return Err(TreeError::InvalidRange);
+        }
+        let mark = Mark {
+            start,
+            end,
+            expand,
+            name,
+            value,
+        };
+        let node_id = self.id_to_node(&string)?;
+        let marks = match &self.nodes[&node_id].data {
+            NodeData::String { marks, .. } => Arc::clone(marks),
+            _ => unreachable!("anchor_string returned a non-string"),
+        };
+        // earlier marks for the attribute that the new one covers can never show through it, and
+        // a null mark that overlaps none of them has nothing to remove
+        let mut kept = Vec::new();
+        let mut overlaps = false;
+        for old in marks.iter() {
+            if old.name == mark.name {
+                if self.mark_covers(&string, &mark, old)? {
+                    continue;
+                }
+                overlaps |= self.marks_overlap(&string, &mark, old)?;
+            }
+            kept.push(old.clone());
+        }
+        if !mark.value.is_null() || overlaps {
+            kept.push(mark);
+        }
+        match &mut self.nodes[&node_id].data {
+            NodeData::String { marks, .. } => *marks = Arc::new(kept),
+            _ => unreachable!("anchor_string returned a non-string"),
+        }
+        Ok(())
+    }
+
+    /// Returns whether every character `inner` applies to, including ones inserted later, is
+    /// also covered by `outer`.
+    fn mark_covers(
+        &self,
+        string: &Id,
+        outer: &Mark<Id>,
+        inner: &Mark<Id>,
+    ) -> Result<bool, TreeError> {
+        Ok(
+            self.edge_order(&outer.start_edge(string), &inner.start_edge(string))?
+                != Ordering::Greater
+                && self.edge_order(&inner.end_edge(string), &outer.end_edge(string))?
+                    != Ordering::Greater,
+        )
+    }
+
+    /// Returns whether `a` and `b` could ever apply to the same character.
+    fn marks_overlap(&self, string: &Id, a: &Mark<Id>, b: &Mark<Id>) -> Result<bool, TreeError> {
+        Ok(
+            self.edge_order(&a.end_edge(string), &b.start_edge(string))? != Ordering::Less
+                && self.edge_order(&b.end_edge(string), &a.start_edge(string))? != Ordering::Less,
+        )
+    }
+
+    /// Compares two edges of marks in the same string, counting deleted characters. Takes
+    /// `O(log n)`.
+    fn edge_order(&self, a: &Edge<'_, Id>, b: &Edge<'_, Id>) -> Result<Ordering, TreeError> {
+        Ok(match (a, b) {
+            (Edge::StringStart, Edge::StringStart) | (Edge::StringEnd, Edge::StringEnd) => {
+                Ordering::Equal
+            }
+            (Edge::StringStart, _) | (_, Edge::StringEnd) => Ordering::Less,
+            (_, Edge::StringStart) | (Edge::StringEnd, _) => Ordering::Greater,
+            (Edge::Char(a_id, a_side), Edge::Char(b_id, b_side)) => {
+                if a_id == b_id {
+                    a_side.cmp(b_side)
+                } else if sequence::in_order(self, a_id, b_id)? {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+        })
+    }
+
+    /// Returns the string that the mark anchor `anchor` is in, which is `anchor` itself if it's a
+    /// string rather than a character.
+    fn anchor_string(&self, anchor: &Id) -> Result<Id, TreeError> {
+        match self.get_type(anchor.clone())? {
+            NodeType::String => Ok(anchor.clone()),
+            NodeType::Character => Ok(self
+                .get_parent(anchor.clone())?
+                .expect("character should have parent")),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+}
+
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> StringRef<Id> {
+    /// Returns the string split into spans of consecutive characters with the same formatting,
+    /// in order. Where marks for the same attribute overlap, the one applied last wins. Takes
+    /// `O(n + m^2)` for a string with `n` characters, including deleted ones, and `m` marks.
+    pub fn spans(&self, tree: &Tree<Id>) -> Result<Vec<Span>, TreeError> {
+        let container = tree.id_to_node(&self.0)?;
+        let (mut segment, marks) = match &tree.nodes[&container].data {
+            NodeData::String { start, marks, .. } => (*start, marks),
+            _ => return Err(TreeError::UnexpectedNodeType),
+        };
+
+        // positions here count tombstones, since marks can be anchored on deleted characters
+        let anchors: HashSet<&Id> = marks
+            .iter()
+            .flat_map(|mark| vec![&mark.start, &mark.end])
+            .collect();
+        let mut anchor_positions = HashMap::new();
+        let mut characters = Vec::new();
+        let mut position = 0;
+        while segment != container {
+            let node = &tree.nodes[&segment];
+            let contents = match &node.data {
+                NodeData::StringSegment { contents, .. } => contents,
+                _ => panic!("node_id listed in segment adjacency was not a string segment"),
+            };
+            for (id, contents_index) in sequence::items(node) {
+                if let Some(index) = contents_index {
+                    let character = contents[index..]
+                        .chars()
+                        .next()
+                        .expect("segment id pointed past end of contents");
+                    characters.push((position, character));
+                }
+                if anchors.contains(&id) {
+                    anchor_positions.insert(id, position);
+                }
+                position += 1;
+            }
+            segment = *node.segment_adjacencies().1;
+        }
+
+        // the range of positions each mark covers, from inclusive to exclusive
+        let ranges: Vec<(usize, usize)> = marks
+            .iter()
+            .map(|mark| {
+                let from = match anchor_positions.get(&mark.start) {
+                    Some(start) if mark.expand.before() => start + 1,
+                    Some(start) => *start,
+                    // anchored on the string itself
+                    None => 0,
+                };
+                let to = match anchor_positions.get(&mark.end) {
+                    Some(end) if mark.expand.after() => *end,
+                    Some(end) => end + 1,
+                    None => position,
+                };
+                (from, to)
+            })
+            .collect();
+        let mut boundaries: Vec<usize> = ranges.iter().flat_map(|(a, b)| vec![*a, *b]).collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut spans: Vec<Span> = Vec::new();
+        let mut attributes = BTreeMap::new();
+        let mut next_boundary = 0;
+        for (position, character) in characters {
+            let crossed = boundaries[next_boundary..]
+                .iter()
+                .take_while(|boundary| **boundary <= position)
+                .count();
+            if crossed > 0 {
+                next_boundary += crossed;
+                attributes = BTreeMap::new();
+                for (mark, (from, to)) in marks.iter().zip(&ranges) {
+                    if *from <= position && position < *to {
+                        if mark.value.is_null() {
+                            attributes.remove(&mark.name);
+                        } else {
+                            attributes.insert(mark.name.clone(), mark.value.clone());
+                        }
+                    }
+                }
+            }
+            match spans.last_mut() {
+                Some(span) if span.attributes == attributes => span.text.push(character),
+                _ => spans.push(Span {
+                    text: character.to_string(),
+                    attributes: attributes.clone(),
+                }),
+            }
+        }
+        Ok(spans)
+    }
+}
@@ -1,92 +1,689 @@
-use super::tree::{Node, NodeId, Tree, TreeError};
+use super::counted::Weight;
+use super::text::TextUnit;
+use super::tree::{IdRange, Node, NodeId, SequentialId, Tree, TreeError};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::ops::Range;
 
 const SPLIT_LEN: usize = 1024;
 
-/// `insert_fn(index to insert in contents at, node to insert into) -> length of inserted item`
-pub(super) fn insert<Id: Hash + Clone + Eq + Debug, F: FnOnce(usize, &mut Node<Id>) -> usize>(
+/// A run of adjacent items in a segment with consecutive ids. Items inserted together, or typed
+/// one after another, share a single run, so segments don't need to store an id per item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct IdRun<Id> {
+    /// Id of the first item in the run. The item `n` places later has id `start.successor(n)`.
+    pub(super) start: Id,
+    /// Number of items in the run. Always at least 1.
+    pub(super) len: usize,
+    /// If the items are live, the index in the segment's contents of the first item. The rest of
+    /// the run's items directly follow it in the contents. If the items are tombstones, `None`.
+    pub(super) contents_index: Option<usize>,
+}
+
+impl<Id: SequentialId + PartialEq> IdRun<Id> {
+    /// If `id` is in this run, returns how many places after `start` it is.
+    pub(super) fn step_of(&self, id: &Id) -> Option<usize> {
+        id.steps_from(&self.start).filter(|step| *step < self.len)
+    }
+
+    /// True if `next` could be appended onto this run, if it came directly after it.
+    fn can_merge(&self, next: &IdRun<Id>) -> bool {
+        self.contents_index.is_some() == next.contents_index.is_some()
+            && self.start.checked_successor(self.len).as_ref() == Some(&next.start)
+    }
+}
+
+/// Iterator over the `(id, contents index)` of every item in a segment, including tombstones,
+/// returned by `items`.
+pub(super) struct SegmentItems<'a, Id: Hash + Clone + Eq + Debug + SequentialId> {
+    node: &'a Node<Id>,
+    runs: std::slice::Iter<'a, IdRun<Id>>,
+    run: Option<&'a IdRun<Id>>,
+    /// Index within `run` of the next item.
+    step: usize,
+    /// Contents index of the next item, if `run` is live.
+    contents_index: Option<usize>,
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> Iterator for SegmentItems<'a, Id> {
+    type Item = (Id, Option<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(run) = self.run {
+                if self.step < run.len {
+                    let id = run.start.successor(self.step);
+                    let contents_index = self.contents_index;
+                    self.contents_index =
+                        contents_index.map(|index| self.node.segment_contents_advance(index, 1));
+                    self.step += 1;
+                    return Some((id, contents_index));
+                }
+            }
+            let run = self.runs.next()?;
+            self.run = Some(run);
+            self.step = 0;
+            self.contents_index = run.contents_index;
+        }
+    }
+}
+
+/// Returns an iterator over the `(id, contents index)` of every item in the segment `node`, in
+/// order. Tombstones have a contents index of `None`.
+pub(super) fn items<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    node: &Node<Id>,
+) -> SegmentItems<'_, Id> {
+    SegmentItems {
+        node,
+        runs: node
+            .segment_ids()
+            .expect("items called on non-segment node")
+            .iter(),
+        run: None,
+        step: 0,
+        contents_index: None,
+    }
+}
+
+/// Inserts `len` items with ids `first_id`, `first_id.successor(1)`, ... directly after
+/// `append_id`, which may be a tombstone, or the container to insert at the start of the
+/// sequence. `insert_fn(index to insert in contents at, node to insert into) -> length of
+/// inserted items in contents`
+pub(super) fn insert<
+    Id: Hash + Clone + Eq + Debug + SequentialId,
+    F: FnOnce(usize, &mut Node<Id>) -> usize,
+>(
     tree: &mut Tree<Id>,
     append_id: Id,
-    character_id: Id,
+    first_id: Id,
+    len: usize,
     insert_fn: F,
 ) -> Result<(), TreeError> {
-    if tree.id_to_node.contains_key(&character_id) {
+    if len == 0 {
+        return Ok(());
+    }
+    if first_id.checked_successor(len - 1).is_none() {
+        return Err(TreeError::IdOverflow);
+    }
+    if (0..len).any(|step| tree.id_to_node.contains_key(&first_id.successor(step))) {
         return Err(TreeError::DuplicateId);
     }
-    let (node_id, string_index, id_list_index) = lookup_insertion_point(tree, &append_id)?;
-    let insert_len = insert_fn(string_index, &mut tree.nodes[&node_id]);
+    let (node_id, run_index) = lookup_insertion_point(tree, &append_id)?;
+    let contents_index = {
+        let node = &tree.nodes[&node_id];
+        node.segment_ids()?[run_index..]
+            .iter()
+            .find_map(|run| run.contents_index)
+            .unwrap_or(node.segment_contents_len()?)
+    };
+    let insert_len = insert_fn(contents_index, &mut tree.nodes[&node_id]);
+    let weight = tree.nodes[&node_id].segment_range_weight(contents_index, insert_len);
     let ids = tree.nodes[&node_id].segment_ids_mut()?;
-    // contents.insert(string_index, character);
-    for (_, index_opt) in ids.iter_mut().skip(id_list_index) {
-        if let Some(index) = index_opt {
+    for run in ids.iter_mut().skip(run_index) {
+        if let Some(index) = &mut run.contents_index {
             *index += insert_len;
         }
     }
-    tree.id_to_node.insert(character_id.clone(), node_id);
-    ids.insert(id_list_index, (character_id, Some(string_index)));
+    for step in 0..len {
+        tree.id_to_node.insert(first_id.successor(step), node_id);
+    }
+    ids.insert(
+        run_index,
+        IdRun {
+            start: first_id,
+            len,
+            contents_index: Some(contents_index),
+        },
+    );
+    merge_around(ids, run_index);
+    adjust_weight(tree, node_id, |old| old + weight);
     consider_split(tree, node_id);
     Ok(())
 }
 
-pub(super) fn delete<Id: Hash + Clone + Eq + Debug, F: FnOnce(usize, &mut Node<Id>) -> usize>(
+/// Deletes the item `char_id`, leaving a tombstone. Deleting a tombstone does nothing.
+/// `delete_fn(range of contents to delete, node to delete from)`
+pub(super) fn delete<
+    Id: Hash + Clone + Eq + Debug + SequentialId,
+    F: FnMut(Range<usize>, &mut Node<Id>),
+>(
     tree: &mut Tree<Id>,
     char_id: Id,
     delete_fn: F,
 ) -> Result<(), TreeError> {
-    let (node_id, id_list_index) = lookup_id_index(tree, &char_id)?;
-    if let Some(old_byte_index) = tree.nodes[&node_id].segment_ids_mut()?[id_list_index]
-        .1
-        .take()
-    {
-        let delete_len = delete_fn(old_byte_index, &mut tree.nodes[&node_id]);
-        for (_, byte_idx) in tree.nodes[&node_id]
-            .segment_ids_mut()?
-            .iter_mut()
-            .skip(id_list_index)
-        {
-            if let Some(byte_idx) = byte_idx {
-                *byte_idx -= delete_len;
+    delete_range(tree, char_id.clone(), char_id, delete_fn)
+}
+
+/// Deletes every item from `start_id` to `end_id` inclusive, in document order, leaving
+/// tombstones. Either end may itself be a tombstone, and items in the range that are already
+/// deleted are skipped. Returns `InvalidRange` if `end_id` comes before `start_id` or the two are
+/// in different sequences. Takes `O(log n + k)` for a range spanning `k` items.
+/// `delete_fn(range of contents to delete, node to delete from)` is called once per segment.
+pub(super) fn delete_range<
+    Id: Hash + Clone + Eq + Debug + SequentialId,
+    F: FnMut(Range<usize>, &mut Node<Id>),
+>(
+    tree: &mut Tree<Id>,
+    start_id: Id,
+    end_id: Id,
+    mut delete_fn: F,
+) -> Result<(), TreeError> {
+    if !in_order(tree, &start_id, &end_id)? {
+        return Err(TreeError::InvalidRange);
+    }
+    let (start_segment, start_item) = lookup_item_index(tree, &start_id)?;
+    let (end_segment, end_item) = lookup_item_index(tree, &end_id)?;
+
+    let mut segment = start_segment;
+    let mut from = start_item;
+    loop {
+        let to = if segment == end_segment {
+            end_item + 1
+        } else {
+            segment_len(&tree.nodes[&segment])
+        };
+        delete_items(tree, segment, from, to, &mut delete_fn);
+        if segment == end_segment {
+            return Ok(());
+        }
+        segment = *tree.nodes[&segment].segment_adjacencies().1;
+        from = 0;
+    }
+}
+
+/// Deletes every item with an id in `ids`, leaving tombstones. The items may be anywhere in the
+/// sequence `container`, with other items between them, and items that are already deleted are
+/// skipped. Returns `InvalidRange` if any of them is in a different sequence, without deleting
+/// anything. Takes `O(k)` for `k` ids, plus a lookup wherever a run of `ids` is split up in the
+/// sequence. `delete_fn(range of contents to delete, node to delete from)`
+pub(super) fn delete_ids<
+    Id: Hash + Clone + Eq + Debug + SequentialId,
+    F: FnMut(Range<usize>, &mut Node<Id>),
+>(
+    tree: &mut Tree<Id>,
+    container: NodeId,
+    ids: &[IdRange<Id>],
+    mut delete_fn: F,
+) -> Result<(), TreeError> {
+    // find everything first, since deleting doesn't move items between segments
+    let mut spans = Vec::new();
+    for run in ids {
+        let mut done = 0;
+        while done < run.len {
+            let id = run
+                .start
+                .checked_successor(done)
+                .ok_or(TreeError::IdOverflow)?;
+            let (segment, run_index, step) = lookup_id_index(tree, &id)?;
+            let node = &tree.nodes[&segment];
+            if node.parent != Some(container) {
+                return Err(TreeError::InvalidRange);
             }
+            let runs = node.segment_ids()?;
+            let from = runs[..run_index].iter().map(|run| run.len).sum::<usize>() + step;
+            let count = (runs[run_index].len - step).min(run.len - done);
+            spans.push((segment, from, from + count));
+            done += count;
         }
     }
+    for (segment, from, to) in spans {
+        delete_items(tree, segment, from, to, &mut delete_fn);
+    }
     Ok(())
 }
 
+/// Returns whether the item `a_id` comes before `b_id` or is the same item, counting tombstones.
+/// Returns `InvalidRange` if the two are in different sequences. Takes `O(log n)`.
+pub(super) fn in_order<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    a_id: &Id,
+    b_id: &Id,
+) -> Result<bool, TreeError> {
+    let (a_segment, a_item) = lookup_item_index(tree, a_id)?;
+    let (b_segment, b_item) = lookup_item_index(tree, b_id)?;
+    let container = tree.nodes[&a_segment]
+        .parent
+        .expect("segment did not have a container");
+    if tree.nodes[&b_segment].parent != Some(container) {
+        return Err(TreeError::InvalidRange);
+    }
+    Ok(if a_segment == b_segment {
+        a_item <= b_item
+    } else {
+        tree.nodes[&container]
+            .container_segments()
+            .is_before(a_segment, b_segment)
+    })
+}
+
+/// Tombstones the items from index `from` up to `to` among all the items in `segment`.
+fn delete_items<
+    Id: Hash + Clone + Eq + Debug + SequentialId,
+    F: FnMut(Range<usize>, &mut Node<Id>),
+>(
+    tree: &mut Tree<Id>,
+    segment: NodeId,
+    from: usize,
+    to: usize,
+    delete_fn: &mut F,
+) {
+    let node = &mut tree.nodes[&segment];
+    // give the deleted items runs of their own
+    let start_run = split_at_item(node, from);
+    let end_run = split_at_item(node, to);
+    let ids = node.segment_ids().unwrap();
+    let contents_start = match ids[start_run..end_run]
+        .iter()
+        .find_map(|run| run.contents_index)
+    {
+        Some(index) => index,
+        // everything in the range was already deleted
+        None => return,
+    };
+    let contents_end = ids[start_run..end_run]
+        .iter()
+        .rev()
+        .find_map(|run| {
+            run.contents_index
+                .map(|index| node.segment_contents_advance(index, run.len))
+        })
+        .expect("range had a live run");
+    let weight = node.segment_range_weight(contents_start, contents_end - contents_start);
+    delete_fn(contents_start..contents_end, node);
+    let ids = node.segment_ids_mut().unwrap();
+    for run in &mut ids[start_run..end_run] {
+        run.contents_index = None;
+    }
+    for run in &mut ids[end_run..] {
+        if let Some(index) = &mut run.contents_index {
+            *index -= contents_end - contents_start;
+        }
+    }
+    merge_all(ids);
+    adjust_weight(tree, segment, |old| old - weight);
+}
+
+/// Splits the run at `run_index` in two, so that its first `step` items stay in the run and the
+/// rest move to a new run directly after it.
+fn split_run<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    node: &mut Node<Id>,
+    run_index: usize,
+    step: usize,
+) {
+    let run = node.segment_ids().unwrap()[run_index].clone();
+    let rest = IdRun {
+        start: run.start.successor(step),
+        len: run.len - step,
+        contents_index: run
+            .contents_index
+            .map(|index| node.segment_contents_advance(index, step)),
+    };
+    let ids = node.segment_ids_mut().unwrap();
+    ids[run_index].len = step;
+    ids.insert(run_index + 1, rest);
+}
+
+/// Splits runs so that a run starts at item `item_index` among all the items in the segment
+/// `node`, returning the index of that run. If `item_index` is the length of the segment, returns
+/// the number of runs.
+fn split_at_item<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    node: &mut Node<Id>,
+    item_index: usize,
+) -> usize {
+    let mut remaining = item_index;
+    for (run_index, run) in node.segment_ids().unwrap().iter().enumerate() {
+        if remaining == 0 {
+            return run_index;
+        }
+        if remaining < run.len {
+            split_run(node, run_index, remaining);
+            return run_index + 1;
+        }
+        remaining -= run.len;
+    }
+    assert_eq!(remaining, 0, "item index was past the end of the segment");
+    node.segment_ids().unwrap().len()
+}
+
+/// Merges every pair of adjacent runs whose ids and liveness line up.
+fn merge_all<Id: Hash + Clone + Eq + Debug + SequentialId>(ids: &mut Vec<IdRun<Id>>) {
+    let mut run_index = 1;
+    while run_index < ids.len() {
+        if ids[run_index - 1].can_merge(&ids[run_index]) {
+            let this = ids.remove(run_index);
+            ids[run_index - 1].len += this.len;
+        } else {
+            run_index += 1;
+        }
+    }
+}
+
+/// Merges the run at `run_index` with its neighbors, if their ids and liveness line up.
+fn merge_around<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    ids: &mut Vec<IdRun<Id>>,
+    run_index: usize,
+) {
+    if run_index + 1 < ids.len() && ids[run_index].can_merge(&ids[run_index + 1]) {
+        let next = ids.remove(run_index + 1);
+        ids[run_index].len += next.len;
+    }
+    if run_index > 0 && ids[run_index - 1].can_merge(&ids[run_index]) {
+        let this = ids.remove(run_index);
+        ids[run_index - 1].len += this.len;
+    }
+}
+
+/// Updates the weight of `segment` in its container's segment index.
+fn adjust_weight<Id: Hash + Clone + Eq + Debug + SequentialId, F: FnOnce(Weight) -> Weight>(
+    tree: &mut Tree<Id>,
+    segment: NodeId,
+    f: F,
+) {
+    let container = tree.nodes[&segment]
+        .parent
+        .expect("segment did not have a container");
+    let segments = tree.nodes[&container].container_segments_mut();
+    let weight = f(segments.weight(segment));
+    segments.set_weight(segment, weight);
+}
+
+/// Returns the node of the sequence container `container_id`.
+fn container_node<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+) -> Result<NodeId, TreeError> {
+    let container = tree.id_to_node(container_id)?;
+    if !tree.nodes[&container].segment_is_container() {
+        return Err(TreeError::UnexpectedNodeType);
+    }
+    Ok(container)
+}
+
+/// Returns the total weight of the live items in the sequence `container_id`.
+pub(super) fn weight<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+) -> Result<Weight, TreeError> {
+    let container = container_node(tree, container_id)?;
+    Ok(tree.nodes[&container].container_segments().total())
+}
+
+/// Returns the number of live items in the sequence `container_id`.
+pub(super) fn len<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+) -> Result<usize, TreeError> {
+    Ok(weight(tree, container_id)?.items)
+}
+
+/// Finds the live item at position `index` in the sequence `container_id`, returning its segment,
+/// its index among all the items in the segment, and its index in the segment's contents.
+/// Returns `None` if `index` is past the end of the sequence. Takes `O(log n)`.
+pub(super) fn locate<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+    index: usize,
+) -> Result<Option<(NodeId, usize, usize)>, TreeError> {
+    let container = container_node(tree, container_id)?;
+    let (segment, before) = match tree.nodes[&container]
+        .container_segments()
+        .find(index, TextUnit::Scalar)
+    {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let (item_index, contents_index) = items(&tree.nodes[&segment])
+        .enumerate()
+        .filter_map(|(i, (_, contents_index))| contents_index.map(|c| (i, c)))
+        .nth(index - before.items)
+        .expect("segment index was out of sync with segment");
+    Ok(Some((segment, item_index, contents_index)))
+}
+
+/// Returns the ids of `len` consecutive live items in the sequence `container_id`, starting with
+/// the item at position `start`. Takes `O(log n + len)`.
+pub(super) fn live_ids<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+    start: usize,
+    len: usize,
+) -> Result<Vec<Id>, TreeError> {
+    match start.checked_add(len) {
+        Some(end) if end <= self::len(tree, container_id)? => {}
+        _ => return Err(TreeError::IndexOutOfBounds),
+    }
+    let mut ids = Vec::with_capacity(len);
+    if len == 0 {
+        return Ok(ids);
+    }
+    let (mut segment, mut item_index, _) =
+        locate(tree, container_id, start)?.expect("start was checked to be in bounds");
+    while ids.len() < len {
+        let node = &tree.nodes[&segment];
+        for (id, contents_index) in items(node).skip(item_index) {
+            if ids.len() == len {
+                break;
+            }
+            if contents_index.is_some() {
+                ids.push(id);
+            }
+        }
+        segment = *node.segment_adjacencies().1;
+        item_index = 0;
+    }
+    Ok(ids)
+}
+
+/// Returns the id of the item that ends exactly `offset` units into the sequence
+/// `container_id`, which is the id that an item must be inserted after to start at `offset`.
+/// Offset `0` returns the container itself. Takes `O(log n)`.
+pub(super) fn index_at<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    container_id: &Id,
+    offset: usize,
+    unit: TextUnit,
+) -> Result<Id, TreeError> {
+    let container = container_node(tree, container_id)?;
+    if offset == 0 {
+        return Ok(container_id.clone());
+    }
+    let (segment, before) = tree.nodes[&container]
+        .container_segments()
+        .find(offset - 1, unit)
+        .ok_or(TreeError::IndexOutOfBounds)?;
+    let node = &tree.nodes[&segment];
+    let mut position = before.get(unit);
+    for (id, contents_index) in items(node) {
+        if let Some(contents_index) = contents_index {
+            position += node.segment_item_weight(contents_index).get(unit);
+            if position == offset {
+                return Ok(id);
+            }
+            if position > offset {
+                return Err(TreeError::NotCharBoundary);
+            }
+        }
+    }
+    panic!("segment index was out of sync with segment");
+}
+
+/// Returns the offset of the end of `item_id` in its sequence, which is the offset that an item
+/// inserted after `item_id` would start at. If `item_id` is a tombstone, this is the offset where
+/// it used to end, and if it's a container, this is `0`. Takes `O(log n)`.
+pub(super) fn offset_of<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    item_id: &Id,
+    unit: TextUnit,
+) -> Result<usize, TreeError> {
+    let node_id = tree.id_to_node(item_id)?;
+    let node = &tree.nodes[&node_id];
+    if node.segment_is_container() {
+        return Ok(0);
+    }
+    let container = node.parent.expect("segment did not have a container");
+    let mut offset = tree.nodes[&container]
+        .container_segments()
+        .weight_before(node_id)
+        .get(unit);
+    for (id, contents_index) in items(node) {
+        if let Some(contents_index) = contents_index {
+            offset += node.segment_item_weight(contents_index).get(unit);
+        }
+        if &id == item_id {
+            return Ok(offset);
+        }
+    }
+    panic!("couldn't find id in list");
+}
+
+/// Returns the segment containing `item_id`, and the item's index in the segment's contents if
+/// it's live.
+pub(super) fn contents_index<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    item_id: &Id,
+) -> Result<(NodeId, Option<usize>), TreeError> {
+    let (node_id, run_index, step) = lookup_id_index(tree, item_id)?;
+    let node = &tree.nodes[&node_id];
+    let contents_index = node.segment_ids()?[run_index]
+        .contents_index
+        .map(|index| node.segment_contents_advance(index, step));
+    Ok((node_id, contents_index))
+}
+
+/// Returns the number of live items before `item_id` in its sequence, and whether `item_id` is
+/// itself live. Takes `O(log n)`.
+pub(super) fn position<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    item_id: &Id,
+) -> Result<(usize, bool), TreeError> {
+    let (node_id, run_index, step) = lookup_id_index(tree, item_id)?;
+    let node = &tree.nodes[&node_id];
+    let ids = node.segment_ids()?;
+    let container = node.parent.expect("segment did not have a container");
+    let live_before: usize = ids[..run_index]
+        .iter()
+        .filter(|run| run.contents_index.is_some())
+        .map(|run| run.len)
+        .sum();
+    let live = ids[run_index].contents_index.is_some();
+    let position = tree.nodes[&container]
+        .container_segments()
+        .weight_before(node_id)
+        .items
+        + live_before
+        + if live { step } else { 0 };
+    Ok((position, live))
+}
+
+/// Returns the id of the nearest live item after `item_id` in its sequence, or before it if
+/// `backwards` is set. `item_id` may be a tombstone, or the container, which counts as being
+/// before every item. Returns `None` if there is no such item. Takes `O(n)`.
+pub(super) fn adjacent_live<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &Tree<Id>,
+    item_id: &Id,
+    backwards: bool,
+) -> Result<Option<Id>, TreeError> {
+    let node_id = tree.id_to_node(item_id)?;
+    let (mut segment, mut skip) = if tree.nodes[&node_id].segment_is_container() {
+        if backwards {
+            return Ok(None);
+        }
+        (*tree.nodes[&node_id].segment_adjacencies().1, 0)
+    } else {
+        let item_index = items(&tree.nodes[&node_id])
+            .position(|(id, _)| &id == item_id)
+            .expect("couldn't find id in list");
+        if backwards {
+            let len = segment_len(&tree.nodes[&node_id]);
+            (node_id, len - item_index)
+        } else {
+            (node_id, item_index + 1)
+        }
+    };
+    while !tree.nodes[&segment].segment_is_container() {
+        let node = &tree.nodes[&segment];
+        let found = if backwards {
+            let segment_items: Vec<_> = items(node).collect();
+            segment_items
+                .into_iter()
+                .rev()
+                .skip(skip)
+                .find(|(_, contents_index)| contents_index.is_some())
+        } else {
+            items(node)
+                .skip(skip)
+                .find(|(_, contents_index)| contents_index.is_some())
+        };
+        if let Some((id, _)) = found {
+            return Ok(Some(id));
+        }
+        let (prev, next) = node.segment_adjacencies();
+        segment = if backwards { *prev } else { *next };
+        skip = 0;
+    }
+    Ok(None)
+}
+
+/// Returns the number of items in a segment, including tombstones.
+fn segment_len<Id: Hash + Clone + Eq + Debug + SequentialId>(node: &Node<Id>) -> usize {
+    node.segment_ids()
+        .expect("segment_len called on non-segment node")
+        .iter()
+        .map(|run| run.len)
+        .sum()
+}
+
 // Inserts a new, empty segment after `to_split`, and returns the usize of the new node.
-fn insert_segment<Id: Hash + Clone + Eq + Debug>(
+fn insert_segment<Id: Hash + Clone + Eq + Debug + SequentialId>(
     tree: &mut Tree<Id>,
     to_split: NodeId,
-    id_split_index: usize,
+    item_split_index: usize,
 ) -> NodeId {
     let new_id = tree.next_id();
     // split old node; insert into tree
     {
+        // find the run to split at, splitting a run in two if it straddles `item_split_index`
+        let mut run_split_index = 0;
+        let mut remaining = item_split_index;
+        for run in tree.nodes[&to_split].segment_ids().unwrap() {
+            if remaining < run.len {
+                break;
+            }
+            remaining -= run.len;
+            run_split_index += 1;
+        }
+        if remaining > 0 {
+            split_run(&mut tree.nodes[&to_split], run_split_index, remaining);
+            run_split_index += 1;
+        }
+
         let parent = tree.nodes[&to_split].parent;
         let mut node = Node {
-            parent: parent,
+            parent,
             data: tree.nodes[&to_split].segment_create(),
         };
         let contents_len = tree.nodes[&to_split].segment_contents_len().unwrap();
-        let split_start_string = tree.nodes[&to_split]
-            .segment_ids_mut()
-            .unwrap()
+        let split_start_string = tree.nodes[&to_split].segment_ids().unwrap()[run_split_index..]
             .iter()
-            .skip(id_split_index)
-            .find_map(|(_, byte_idx)| byte_idx.clone())
+            .find_map(|run| run.contents_index)
             .unwrap_or(contents_len);
-        let new_ids: Vec<(Id, Option<usize>)> = tree.nodes[&to_split]
+        let new_ids: Vec<IdRun<Id>> = tree.nodes[&to_split]
             .segment_ids_mut()
             .unwrap()
-            .split_off(id_split_index)
+            .split_off(run_split_index)
             .into_iter()
-            .map(|(id, n)| (id, n.map(|n| n - split_start_string)))
+            .map(|run| IdRun {
+                contents_index: run.contents_index.map(|n| n - split_start_string),
+                ..run
+            })
             .collect();
-        tree.nodes[&to_split].segment_split_contents_into(&mut node, split_start_string);
-        for (id, _) in &new_ids {
-            tree.id_to_node[id] = new_id;
+        for run in &new_ids {
+            for step in 0..run.len {
+                tree.id_to_node[&run.start.successor(step)] = new_id;
+            }
         }
         *node.segment_ids_mut().unwrap() = new_ids;
+        tree.nodes[&to_split].segment_split_contents_into(&mut node, split_start_string);
         tree.nodes.insert(new_id, node);
     }
 
@@ -111,14 +708,31 @@ fn insert_segment<Id: Hash + Clone + Eq + Debug>(
         *prev = new_id;
     }
 
+    // add the new node to the segment index, moving its weight over from `to_split`
+    {
+        let container = tree.nodes[&new_id]
+            .parent
+            .expect("segment did not have a container");
+        let weight = tree.nodes[&new_id].segment_weight();
+        let next = if old_to_split_next == container {
+            None
+        } else {
+            Some(old_to_split_next)
+        };
+        let segments = tree.nodes[&container].container_segments_mut();
+        segments.set_weight(to_split, segments.weight(to_split) - weight);
+        segments.insert_after(to_split, next, new_id, weight);
+    }
+
     new_id
 }
 
-fn lookup_id_index<Id: Hash + Clone + Eq + Debug>(
+/// Looks up the `(containing segment id, run index, index within run)` of an item.
+fn lookup_id_index<Id: Hash + Clone + Eq + Debug + SequentialId>(
     tree: &Tree<Id>,
     lookup_id: &Id,
-) -> Result<(NodeId, usize), TreeError> {
-    let node_id = tree.id_to_node(&lookup_id)?;
+) -> Result<(NodeId, usize, usize), TreeError> {
+    let node_id = tree.id_to_node(lookup_id)?;
     let node = tree
         .nodes
         .get(&node_id)
@@ -126,57 +740,54 @@ fn lookup_id_index<Id: Hash + Clone + Eq + Debug>(
 
     let ids = node.segment_ids()?;
 
-    for (i, (id, _)) in ids.iter().enumerate() {
-        if id == lookup_id {
-            return Ok((node_id, i));
-            // don't check for string index until next iteration of loop; we want the *next*
-            // char index to be the insertion point, not this one
+    for (i, run) in ids.iter().enumerate() {
+        if let Some(step) = run.step_of(lookup_id) {
+            return Ok((node_id, i, step));
         }
     }
     panic!("couldn't find id in list");
 }
 
-/// From a character id, looks up the `(containing segment id, character index, id list index)`
-/// that an appended character would need to be inserted at
-fn lookup_insertion_point<Id: Hash + Clone + Eq + Debug>(
+/// Looks up the `(containing segment id, index among all the items in the segment)` of an item.
+fn lookup_item_index<Id: Hash + Clone + Eq + Debug + SequentialId>(
     tree: &Tree<Id>,
     lookup_id: &Id,
-) -> Result<(NodeId, usize, usize), TreeError> {
-    let node_id = tree.id_to_node(&lookup_id)?;
+) -> Result<(NodeId, usize), TreeError> {
+    let (node_id, run_index, step) = lookup_id_index(tree, lookup_id)?;
+    let before: usize = tree.nodes[&node_id].segment_ids()?[..run_index]
+        .iter()
+        .map(|run| run.len)
+        .sum();
+    Ok((node_id, before + step))
+}
+
+/// From an item id, looks up the `(containing segment id, run index)` that an appended run would
+/// need to be inserted at. If the item is in the middle of a run, the run is split in two.
+fn lookup_insertion_point<Id: Hash + Clone + Eq + Debug + SequentialId>(
+    tree: &mut Tree<Id>,
+    lookup_id: &Id,
+) -> Result<(NodeId, usize), TreeError> {
+    let node_id = tree.id_to_node(lookup_id)?;
     let node = tree
         .nodes
         .get(&node_id)
         .expect("node_id listed in id_to_node did not exist.");
     if node.segment_is_container() {
         let (_, start) = node.segment_adjacencies();
-        return Ok((*start, 0, 0));
-    }
-    let ids = node.segment_ids()?;
-
-    let mut id_list_index_opt = None;
-    for (i, (id, string_index_opt)) in ids.iter().enumerate() {
-        if let Some(id_list_index) = id_list_index_opt {
-            if let Some(string_index) = string_index_opt {
-                return Ok((node_id, *string_index, id_list_index));
-            }
-        }
-        if id == lookup_id {
-            id_list_index_opt = Some(i + 1);
-            // don't check for string index until next iteration of loop; we want the *next*
-            // char index to be the insertion point, not this one
-        }
+        return Ok((*start, 0));
     }
-    if let Some(id_list_index) = id_list_index_opt {
-        return Ok((node_id, node.segment_contents_len()?, id_list_index));
+    let (_, run_index, step) = lookup_id_index(tree, lookup_id)?;
+    if step + 1 < tree.nodes[&node_id].segment_ids()?[run_index].len {
+        split_run(&mut tree.nodes[&node_id], run_index, step + 1);
     }
-    panic!("id not found in segment id list");
+    Ok((node_id, run_index + 1))
 }
 
 /// If `segment` is greater than `SPLIT_LEN`, we'll split it into two pieces. This recurses on
 /// the children, further splitting them if they're still too long. Returns the leftmost and
 /// rightmost of the new segments; if no split occured, these will both still be `segment`.
 // TODO this could probably be sped up to instantly segment a very long node into `n` children.
-fn consider_split<Id: Hash + Clone + Eq + Debug>(
+fn consider_split<Id: Hash + Clone + Eq + Debug + SequentialId>(
     tree: &mut Tree<Id>,
     segment: NodeId,
 ) -> (NodeId, NodeId) {
@@ -184,14 +795,11 @@ fn consider_split<Id: Hash + Clone + Eq + Debug>(
         // abort if this is off the edge of a string
         return (segment, segment);
     }
-    let ids = tree.nodes[&segment].segment_ids_mut().unwrap();
-    if ids.len() <= SPLIT_LEN {
+    let len = segment_len(&tree.nodes[&segment]);
+    if len <= SPLIT_LEN {
         return (segment, segment);
     }
-    // the first index of the second segment. need to do this stuff to make sure we split
-    // along a codepoint boundary
-    let split_start_vec = ids.len() / 2;
-    let new_node_id = insert_segment(tree, segment, split_start_vec);
+    let new_node_id = insert_segment(tree, segment, len / 2);
     let (left, _) = consider_split(tree, segment);
     let (_, right) = consider_split(tree, new_node_id);
     (left, right)
@@ -1,8 +1,22 @@
+use super::export::BytesEncoding;
+use super::marks::{Expand, Span};
+use super::text::{LineColumn, TextUnit};
 use super::tree::*;
 use super::value::{self, Value};
+use crate::{Doc, SiteId, Transaction, ROOT_ID};
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 struct MyId(usize);
 
+impl SequentialId for MyId {
+    fn checked_successor(&self, n: usize) -> Option<MyId> {
+        self.0.checked_add(n).map(MyId)
+    }
+
+    fn steps_from(&self, start: &MyId) -> Option<usize> {
+        self.0.checked_sub(start.0)
+    }
+}
+
 fn vals_to_nums<Id>(vals: Vec<Value<Id>>) -> Vec<i64> {
     vals.iter()
         .map(|val| match val {
@@ -12,6 +26,36 @@ fn vals_to_nums<Id>(vals: Vec<Value<Id>>) -> Vec<i64> {
         .collect()
 }
 
+/// Creates two replicas of a `Doc` that both start with the op made by `setup`, and returns them
+/// with whatever `setup` returned.
+fn replicas<T>(setup: impl FnOnce(&mut Transaction) -> Result<T, TreeError>) -> (Doc, Doc, T) {
+    let mut doc_a = Doc::new_with_site(SiteId([1; 16]));
+    let mut doc_b = Doc::new_with_site(SiteId([2; 16]));
+    let mut shared = None;
+    let op = doc_a
+        .transact(|tx| {
+            shared = Some(setup(tx)?);
+            Ok(())
+        })
+        .unwrap();
+    doc_b.update(op);
+    (doc_a, doc_b, shared.unwrap())
+}
+
+/// Setup for `replicas` that puts the array `[0, 1, 2, 3]` at `"list"`.
+fn create_list(tx: &mut Transaction) -> Result<value::ArrayRef<crate::Id>, TreeError> {
+    let array = tx.create_array()?;
+    tx.set(
+        &value::ObjectRef(ROOT_ID),
+        "list",
+        Value::Array(array.clone()),
+    )?;
+    for i in 0..4 {
+        tx.push(&array, Value::Int(i))?;
+    }
+    Ok(array)
+}
+
 #[test]
 fn object_assignment() {
     let mut tree = Tree::new_with_object_root(MyId(0));
@@ -229,7 +273,7 @@ fn insert_and_delete_characters() {
             .unwrap();
     }
 
-    let long_insert = (5..10000).map(|i| num_to_char(i)).collect::<String>();
+    let long_insert = (5..10000).map(num_to_char).collect::<String>();
     assert_eq!(
         value::StringRef(MyId(0)).to_string(&tree),
         Ok(format!("d{}acb", long_insert))
@@ -241,7 +285,7 @@ fn insert_and_delete_characters() {
 
     assert_eq!(
         value::StringRef(MyId(0)).to_string(&tree),
-        Ok(format!("dacb"))
+        Ok("dacb".to_string())
     );
 }
 
@@ -281,7 +325,7 @@ fn insert_and_delete_list_of_nums() {
             .unwrap();
     }
 
-    let mut long_insert = (5..10000).map(|i| i).collect::<Vec<_>>();
+    let mut long_insert = (5..10000).collect::<Vec<_>>();
     long_insert.insert(0, 4);
     long_insert.push(1);
     long_insert.push(3);
@@ -458,3 +502,1881 @@ fn adjacent_string_index() {
         }
     }
 }
+
+#[test]
+fn export_to_json() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    tree.update(&Edit::TextCreate {
+        id: value::StringRef(MyId(1)),
+    })
+    .unwrap();
+    tree.insert_character(MyId(1), MyId(2), 'h').unwrap();
+    tree.insert_character(MyId(2), MyId(3), 'i').unwrap();
+    tree.update(&Edit::ArrayCreate {
+        id: value::ArrayRef(MyId(4)),
+    })
+    .unwrap();
+    tree.insert_list_item(MyId(4), MyId(5), Value::String(value::StringRef(MyId(1))))
+        .unwrap();
+    tree.insert_list_item(MyId(5), MyId(6), Value::Int(-2))
+        .unwrap();
+    tree.insert_list_item(MyId(6), MyId(7), Value::Null)
+        .unwrap();
+    for (key, item) in [
+        ("b", Value::Array(value::ArrayRef(MyId(4)))),
+        ("a", Value::True),
+        ("c", Value::False),
+    ]
+    .iter()
+    .cloned()
+    {
+        tree.update(&Edit::MapInsert {
+            parent: value::ObjectRef(MyId(0)),
+            key: key.to_string(),
+            item,
+        })
+        .unwrap();
+    }
+
+    assert_eq!(
+        tree.to_json(&tree.root_value()),
+        Ok(serde_json::json!({"a": true, "b": ["hi", -2, null], "c": false}))
+    );
+    assert_eq!(
+        tree.to_json_string(&tree.root_value()),
+        Ok(r#"{"a":true,"b":["hi",-2,null],"c":false}"#.to_string())
+    );
+    assert_eq!(
+        tree.to_json(&Value::String(value::StringRef(MyId(1)))),
+        Ok(serde_json::json!("hi"))
+    );
+    assert_eq!(
+        tree.to_json(&Value::Object(value::ObjectRef(MyId(1)))),
+        Err(TreeError::UnexpectedNodeType)
+    );
+}
+
+#[test]
+fn object_keys_and_entries() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let object = value::ObjectRef(MyId(0));
+    assert_eq!(object.len(&tree), Ok(0));
+    assert_eq!(object.is_empty(&tree), Ok(true));
+    tree.update(&Edit::MapCreate {
+        id: value::ObjectRef(MyId(1)),
+    })
+    .unwrap();
+    for (key, item) in [
+        ("zebra", Value::Int(1)),
+        ("apple", Value::Object(value::ObjectRef(MyId(1)))),
+        ("mango", Value::Null),
+        ("banana", Value::True),
+        ("mango", Value::Unset),
+    ]
+    .iter()
+    .cloned()
+    {
+        tree.update(&Edit::MapInsert {
+            parent: object.clone(),
+            key: key.to_string(),
+            item,
+        })
+        .unwrap();
+    }
+
+    assert_eq!(object.len(&tree), Ok(3));
+    assert_eq!(object.is_empty(&tree), Ok(false));
+    assert_eq!(object.contains_key(&tree, "apple"), Ok(true));
+    assert_eq!(object.contains_key(&tree, "mango"), Ok(false));
+    assert_eq!(
+        object.keys(&tree),
+        Ok(vec![
+            "apple".to_string(),
+            "banana".to_string(),
+            "zebra".to_string()
+        ])
+    );
+    assert_eq!(
+        object.entries(&tree).unwrap().collect::<Vec<_>>(),
+        vec![
+            (
+                "apple".to_string(),
+                Value::Object(value::ObjectRef(MyId(1)))
+            ),
+            ("banana".to_string(), Value::True),
+            ("zebra".to_string(), Value::Int(1)),
+        ]
+    );
+    assert_eq!(
+        value::ObjectRef(MyId(2)).keys(&tree),
+        Err(TreeError::UnknownId)
+    );
+}
+
+#[test]
+fn array_indexed_access() {
+    let mut tree = Tree::new_with_array_root(MyId(0));
+    let array = value::ArrayRef(MyId(0));
+    assert_eq!(Ok(0), array.len(&tree));
+    assert_eq!(Ok(true), array.is_empty(&tree));
+    assert_eq!(Ok(Value::Unset), array.get(&tree, 0));
+    assert_eq!(
+        Err(TreeError::IndexOutOfBounds),
+        array.index_to_id(&tree, 0)
+    );
+
+    // enough entries to span several segments
+    for i in 1..3000 {
+        tree.update(&Edit::ArrayInsert {
+            index: value::ArrayIndex(MyId(i - 1)),
+            id: value::ArrayIndex(MyId(i)),
+            item: Value::Int(i as i64),
+        })
+        .unwrap();
+    }
+    for i in (1..3000).step_by(2) {
+        tree.update(&Edit::ArrayDelete {
+            id: value::ArrayIndex(MyId(i)),
+        })
+        .unwrap();
+    }
+
+    assert_eq!(Ok(1499), array.len(&tree));
+    assert_eq!(Ok(false), array.is_empty(&tree));
+    assert_eq!(Ok(Value::Int(2)), array.get(&tree, 0));
+    assert_eq!(Ok(Value::Int(2000)), array.get(&tree, 999));
+    assert_eq!(Ok(Value::Unset), array.get(&tree, 1499));
+    assert_eq!(
+        Ok(value::ArrayIndex(MyId(2000))),
+        array.index_to_id(&tree, 999)
+    );
+    assert_eq!(
+        Ok(Some(999)),
+        array.id_to_index(&tree, &value::ArrayIndex(MyId(2000)))
+    );
+    assert_eq!(
+        Ok(None),
+        array.id_to_index(&tree, &value::ArrayIndex(MyId(2001)))
+    );
+    assert_eq!(
+        Err(TreeError::UnknownId),
+        array.id_to_index(&tree, &value::ArrayIndex(MyId(0)))
+    );
+
+    let entries: Vec<_> = array.iter(&tree).unwrap().take(3).collect();
+    assert_eq!(
+        entries,
+        vec![
+            (value::ArrayIndex(MyId(2)), Value::Int(2)),
+            (value::ArrayIndex(MyId(4)), Value::Int(4)),
+            (value::ArrayIndex(MyId(6)), Value::Int(6)),
+        ]
+    );
+    for (i, (id, _)) in array.iter(&tree).unwrap().enumerate() {
+        assert_eq!(Ok(Some(i)), array.id_to_index(&tree, &id));
+    }
+    assert_eq!(
+        Ok(vec![MyId(2), MyId(4)]),
+        tree.sequence_ids(&MyId(0), 0, 2)
+    );
+    assert_eq!(
+        Err(TreeError::IndexOutOfBounds),
+        tree.sequence_ids(&MyId(0), 1, usize::MAX)
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        value::ArrayRef(MyId(2)).len(&tree)
+    );
+}
+
+#[test]
+fn string_offsets() {
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    assert_eq!(Ok(0), string.len(&tree));
+    assert_eq!(Ok(value::StringIndex(MyId(0))), string.index_at(&tree, 0));
+    assert_eq!(Ok(0), value::StringIndex(MyId(0)).offset(&tree));
+    assert_eq!(Err(TreeError::IndexOutOfBounds), string.index_at(&tree, 1));
+
+    // enough characters to span several segments, with every other one deleted
+    for i in 1..3000 {
+        tree.update(&Edit::TextInsert {
+            index: value::StringIndex(MyId(i - 1)),
+            id: value::StringIndex(MyId(i)),
+            character: 'é',
+        })
+        .unwrap();
+    }
+    for i in (1..3000).step_by(2) {
+        tree.update(&Edit::TextDelete {
+            id: value::StringIndex(MyId(i)),
+        })
+        .unwrap();
+    }
+
+    assert_eq!(Ok(1499), string.len(&tree));
+    assert_eq!(Ok(false), string.is_empty(&tree));
+    assert_eq!(Ok(value::StringIndex(MyId(2))), string.index_at(&tree, 1));
+    assert_eq!(
+        Ok(value::StringIndex(MyId(2998))),
+        string.index_at(&tree, 1499)
+    );
+    for offset in 0..1500 {
+        let index = string.index_at(&tree, offset).unwrap();
+        assert_eq!(Ok(offset), index.offset(&tree));
+    }
+    // deleted characters resolve to the offset of the preceding live character
+    assert_eq!(Ok(0), value::StringIndex(MyId(1)).offset(&tree));
+    assert_eq!(Ok(500), value::StringIndex(MyId(1001)).offset(&tree));
+
+    // inserting at an offset lands the character there
+    let anchor = string.index_at(&tree, 3).unwrap();
+    tree.update(&Edit::TextInsert {
+        index: anchor,
+        id: value::StringIndex(MyId(5000)),
+        character: 'x',
+    })
+    .unwrap();
+    assert_eq!(Ok(4), value::StringIndex(MyId(5000)).offset(&tree));
+    assert_eq!("éééx", &string.to_string(&tree).unwrap()[.."éééx".len()]);
+}
+
+#[test]
+fn text_unit_offsets() {
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    // 'é' is 2 UTF-8 bytes, '😀' and '𝄞' are 4 UTF-8 bytes and 2 UTF-16 code units
+    let text = "a😀é\n𝄞\nb";
+    for (i, character) in text.chars().enumerate() {
+        tree.update(&Edit::TextInsert {
+            index: value::StringIndex(MyId(i)),
+            id: value::StringIndex(MyId(i + 1)),
+            character,
+        })
+        .unwrap();
+    }
+    assert_eq!(Ok(text.len()), string.len_in(&tree, TextUnit::Utf8));
+    assert_eq!(
+        Ok(text.encode_utf16().count()),
+        string.len_in(&tree, TextUnit::Utf16)
+    );
+    assert_eq!(Ok(7), string.len_in(&tree, TextUnit::Scalar));
+
+    // cursor after the emoji
+    let after_emoji = value::StringIndex(MyId(2));
+    assert_eq!(Ok(5), after_emoji.offset_in(&tree, TextUnit::Utf8));
+    assert_eq!(Ok(3), after_emoji.offset_in(&tree, TextUnit::Utf16));
+    assert_eq!(Ok(2), after_emoji.offset_in(&tree, TextUnit::Scalar));
+    assert_eq!(
+        Ok(after_emoji.clone()),
+        string.index_at_unit(&tree, 5, TextUnit::Utf8)
+    );
+    assert_eq!(
+        Ok(after_emoji.clone()),
+        string.index_at_unit(&tree, 3, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(after_emoji),
+        string.index_at_unit(&tree, 2, TextUnit::Scalar)
+    );
+
+    // offsets inside a character are rejected
+    assert_eq!(
+        Err(TreeError::NotCharBoundary),
+        string.index_at_unit(&tree, 2, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Err(TreeError::NotCharBoundary),
+        string.index_at_unit(&tree, 3, TextUnit::Utf8)
+    );
+    assert_eq!(
+        Err(TreeError::IndexOutOfBounds),
+        string.index_at_unit(&tree, 10, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(value::StringIndex(MyId(7))),
+        string.index_at_unit(&tree, 9, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(value::StringIndex(MyId(0))),
+        string.index_at_unit(&tree, 0, TextUnit::Utf8)
+    );
+
+    // every UTF-16 offset on a boundary roundtrips
+    let mut utf16_offset = 0;
+    for character in text.chars() {
+        let index = string
+            .index_at_unit(&tree, utf16_offset, TextUnit::Utf16)
+            .unwrap();
+        assert_eq!(Ok(utf16_offset), index.offset_in(&tree, TextUnit::Utf16));
+        utf16_offset += character.len_utf16();
+    }
+
+    // line and column
+    let after_clef = value::StringIndex(MyId(5));
+    assert_eq!(
+        Ok(LineColumn { line: 1, column: 2 }),
+        after_clef.line_column(&tree, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(LineColumn { line: 1, column: 1 }),
+        after_clef.line_column(&tree, TextUnit::Scalar)
+    );
+    assert_eq!(
+        Ok(after_clef),
+        string.index_at_line_column(&tree, LineColumn { line: 1, column: 2 }, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(value::StringIndex(MyId(0))),
+        string.index_at_line_column(&tree, LineColumn { line: 0, column: 0 }, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(value::StringIndex(MyId(7))),
+        string.index_at_line_column(&tree, LineColumn { line: 2, column: 1 }, TextUnit::Utf8)
+    );
+    assert_eq!(
+        Err(TreeError::NotCharBoundary),
+        string.index_at_line_column(&tree, LineColumn { line: 1, column: 1 }, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Err(TreeError::IndexOutOfBounds),
+        string.index_at_line_column(&tree, LineColumn { line: 0, column: 9 }, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Err(TreeError::IndexOutOfBounds),
+        string.index_at_line_column(&tree, LineColumn { line: 3, column: 0 }, TextUnit::Utf16)
+    );
+
+    // deleted characters resolve to where they used to be
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(2)),
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(1),
+        value::StringIndex(MyId(2)).offset_in(&tree, TextUnit::Utf16)
+    );
+    assert_eq!(
+        Ok(3),
+        value::StringIndex(MyId(3)).offset_in(&tree, TextUnit::Utf8)
+    );
+}
+
+#[test]
+fn positional_lookups_match_model() {
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    // (id, character) of each live character, in order
+    let mut model: Vec<(usize, char)> = Vec::new();
+    let mut next_id = 1;
+    // a long run of appends splits the last segment many times, which exhausts the order labels
+    // at the end of the string and forces a relabel
+    for i in 0..40_000 {
+        let character = if i % 7 == 0 { '😀' } else { 'a' };
+        let index = model.last().map(|(id, _)| *id).unwrap_or(0);
+        tree.update(&Edit::TextInsert {
+            index: value::StringIndex(MyId(index)),
+            id: value::StringIndex(MyId(next_id)),
+            character,
+        })
+        .unwrap();
+        model.push((next_id, character));
+        next_id += 1;
+    }
+    let snapshot = tree.clone();
+    // then scatter inserts and deletes through the middle
+    for i in 0..2000 {
+        let position = (i * 7919) % model.len();
+        if i % 100 == 1 {
+            // long enough to span several segments
+            let end = (position + 1500).min(model.len() - 1);
+            tree.update(&Edit::TextDeleteRange {
+                ids: IdRange::from_ids(model[position..=end].iter().map(|(id, _)| MyId(*id))),
+            })
+            .unwrap();
+            model.drain(position..=end);
+        } else if i % 3 == 0 {
+            let (id, _) = model.remove(position);
+            tree.update(&Edit::TextDelete {
+                id: value::StringIndex(MyId(id)),
+            })
+            .unwrap();
+        } else {
+            let anchor = string.index_at(&tree, position).unwrap();
+            tree.update(&Edit::TextInsert {
+                index: anchor,
+                id: value::StringIndex(MyId(next_id)),
+                character: 'é',
+            })
+            .unwrap();
+            model.insert(position, (next_id, 'é'));
+            next_id += 1;
+        }
+    }
+
+    assert_eq!(Ok(model.len()), string.len(&tree));
+    let text: String = model.iter().map(|(_, c)| c).collect();
+    assert_eq!(Ok(text.len()), string.len_in(&tree, TextUnit::Utf8));
+    assert_eq!(
+        Ok(text.encode_utf16().count()),
+        string.len_in(&tree, TextUnit::Utf16)
+    );
+    let mut utf16_offset = 0;
+    for (position, (id, character)) in model.iter().enumerate() {
+        utf16_offset += character.len_utf16();
+        if position % 97 != 0 {
+            continue;
+        }
+        let index = value::StringIndex(MyId(*id));
+        assert_eq!(Ok(index.clone()), string.index_at(&tree, position + 1));
+        assert_eq!(Ok(position + 1), index.offset(&tree));
+        assert_eq!(
+            Ok(index.clone()),
+            string.index_at_unit(&tree, utf16_offset, TextUnit::Utf16)
+        );
+        assert_eq!(Ok(utf16_offset), index.offset_in(&tree, TextUnit::Utf16));
+    }
+
+    // clones made before the edits are unaffected
+    assert_eq!(Ok(40_000), string.len(&snapshot));
+    assert_eq!(
+        Ok(value::StringIndex(MyId(40_000))),
+        string.index_at(&snapshot, 40_000)
+    );
+}
+
+#[test]
+fn text_insert_runs() {
+    fn run_count(tree: &Tree<MyId>) -> usize {
+        tree.nodes
+            .values()
+            .map(|node| node.segment_ids().map(|ids| ids.len()).unwrap_or(0))
+            .sum()
+    }
+
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(100)),
+        text: "hello wörld".to_string(),
+    })
+    .unwrap();
+    assert_eq!("hello wörld", string.to_string(&tree).unwrap());
+    assert_eq!(1, run_count(&tree));
+
+    // characters inside the run can be used as anchors and deleted
+    tree.update(&Edit::TextInsert {
+        index: value::StringIndex(MyId(104)),
+        id: value::StringIndex(MyId(1)),
+        character: ',',
+    })
+    .unwrap();
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(107)),
+    })
+    .unwrap();
+    assert_eq!("hello, wrld", string.to_string(&tree).unwrap());
+    assert_eq!(Ok(5), value::StringIndex(MyId(104)).offset(&tree));
+    assert_eq!(Ok(8), value::StringIndex(MyId(107)).offset(&tree));
+    assert_eq!(Ok(value::StringIndex(MyId(108))), string.index_at(&tree, 9));
+    assert!(!value::StringIndex(MyId(107)).still_exists(&tree));
+    assert_eq!(
+        Ok(value::StringIndex(MyId(108))),
+        value::StringIndex(MyId(106)).adjacent(&tree, 1)
+    );
+    // "hello" ","  " w"  "ö"  "rld"
+    assert_eq!(5, run_count(&tree));
+
+    // deleting the rest of the run merges the tombstones back together
+    for id in (100..107).chain(108..111) {
+        tree.update(&Edit::TextDelete {
+            id: value::StringIndex(MyId(id)),
+        })
+        .unwrap();
+    }
+    assert_eq!(",", string.to_string(&tree).unwrap());
+    assert_eq!(3, run_count(&tree));
+
+    // characters typed one after another with consecutive ids share a run
+    for i in 0..10 {
+        tree.update(&Edit::TextInsert {
+            index: value::StringIndex(MyId(if i == 0 { 1 } else { 199 + i })),
+            id: value::StringIndex(MyId(200 + i)),
+            character: 'x',
+        })
+        .unwrap();
+    }
+    assert_eq!(",xxxxxxxxxx", string.to_string(&tree).unwrap());
+    assert_eq!(4, run_count(&tree));
+
+    // runs can't reuse ids, and a failed run leaves the tree unchanged
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::TextInsertRun {
+            index: value::StringIndex(MyId(0)),
+            id: value::StringIndex(MyId(195)),
+            text: "abcdef".to_string(),
+        })
+    );
+    assert_eq!(",xxxxxxxxxx", string.to_string(&tree).unwrap());
+    assert_eq!(Err(TreeError::UnknownId), tree.get_type(MyId(195)));
+
+    // runs whose ids would overflow are rejected, but a run may end on the largest id
+    assert_eq!(
+        Err(TreeError::IdOverflow),
+        tree.update(&Edit::TextInsertRun {
+            index: value::StringIndex(MyId(0)),
+            id: value::StringIndex(MyId(usize::MAX - 2)),
+            text: "abcdef".to_string(),
+        })
+    );
+    assert_eq!(",xxxxxxxxxx", string.to_string(&tree).unwrap());
+    assert_eq!(
+        Err(TreeError::UnknownId),
+        tree.get_type(MyId(usize::MAX - 2))
+    );
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(usize::MAX - 1)),
+        text: "ab".to_string(),
+    })
+    .unwrap();
+    assert_eq!("ab,xxxxxxxxxx", string.to_string(&tree).unwrap());
+}
+
+#[test]
+fn long_text_insert_runs_are_split() {
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    let text: String = (0..100_000)
+        .map(|i| std::char::from_u32('a' as u32 + (i % 26)).unwrap())
+        .collect();
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(1)),
+        text: text.clone(),
+    })
+    .unwrap();
+    assert_eq!(Ok(text.clone()), string.to_string(&tree));
+    assert_eq!(Ok(100_000), string.len(&tree));
+    assert!(tree.nodes.len() > 100_000 / 1024);
+    for offset in (0..100_000).step_by(997) {
+        let index = string.index_at(&tree, offset + 1).unwrap();
+        assert_eq!(value::StringIndex(MyId(offset + 1)), index);
+        assert_eq!(Ok(offset + 1), index.offset(&tree));
+    }
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(50_000)),
+    })
+    .unwrap();
+    assert_eq!(Ok(99_999), string.len(&tree));
+    assert_eq!(
+        Ok(value::StringIndex(MyId(50_001))),
+        string.index_at(&tree, 50_000)
+    );
+}
+
+#[test]
+fn range_deletes() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    tree.update(&Edit::TextCreate {
+        id: value::StringRef(MyId(1)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayCreate {
+        id: value::ArrayRef(MyId(2)),
+    })
+    .unwrap();
+    let string = value::StringRef(MyId(1));
+    let text: String = (0..3000)
+        .map(|i| if i % 2 == 0 { 'a' } else { 'b' })
+        .collect();
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(1)),
+        id: value::StringIndex(MyId(100)),
+        text,
+    })
+    .unwrap();
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(100)),
+    })
+    .unwrap();
+
+    // the range spans several segments, and its start is already a tombstone
+    tree.update(&Edit::TextDeleteRange {
+        ids: vec![IdRange {
+            start: MyId(100),
+            len: 2997,
+        }],
+    })
+    .unwrap();
+    assert_eq!(Ok("bab".to_string()), string.to_string(&tree));
+    assert!(!value::StringIndex(MyId(1500)).still_exists(&tree));
+    // deleting a range of tombstones does nothing
+    tree.update(&Edit::TextDeleteRange {
+        ids: vec![IdRange {
+            start: MyId(200),
+            len: 101,
+        }],
+    })
+    .unwrap();
+    assert_eq!(Ok(3), string.len(&tree));
+
+    // only the listed items are deleted, so items inserted between them survive
+    tree.update(&Edit::TextInsert {
+        index: value::StringIndex(MyId(3097)),
+        id: value::StringIndex(MyId(10)),
+        character: 'x',
+    })
+    .unwrap();
+    tree.update(&Edit::TextDeleteRange {
+        ids: vec![IdRange {
+            start: MyId(3097),
+            len: 2,
+        }],
+    })
+    .unwrap();
+    assert_eq!(Ok("xb".to_string()), string.to_string(&tree));
+
+    // ids across sequences, ids of the wrong type and unknown ids fail without deleting anything
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::TextDeleteRange {
+            ids: vec![IdRange {
+                start: MyId(1),
+                len: 1,
+            }],
+        })
+    );
+    tree.update(&Edit::TextCreate {
+        id: value::StringRef(MyId(12)),
+    })
+    .unwrap();
+    tree.insert_character(MyId(12), MyId(13), 'z').unwrap();
+    assert_eq!(
+        Err(TreeError::InvalidRange),
+        tree.update(&Edit::TextDeleteRange {
+            ids: IdRange::from_ids(vec![MyId(10), MyId(13)]),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnknownId),
+        tree.update(&Edit::TextDeleteRange {
+            ids: IdRange::from_ids(vec![MyId(10), MyId(5000)]),
+        })
+    );
+    assert_eq!(Ok("xb".to_string()), string.to_string(&tree));
+
+    for i in 0..5 {
+        tree.update(&Edit::MapCreate {
+            id: value::ObjectRef(MyId(20 + i)),
+        })
+        .unwrap();
+        tree.update(&Edit::ArrayInsert {
+            index: value::ArrayIndex(MyId(if i == 0 { 2 } else { 29 + i })),
+            id: value::ArrayIndex(MyId(30 + i)),
+            item: Value::Object(value::ObjectRef(MyId(20 + i))),
+        })
+        .unwrap();
+    }
+    let array = value::ArrayRef(MyId(2));
+    tree.update(&Edit::ArrayDeleteRange {
+        ids: vec![IdRange {
+            start: MyId(31),
+            len: 3,
+        }],
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(vec![
+            Value::Object(value::ObjectRef(MyId(20))),
+            Value::Object(value::ObjectRef(MyId(24))),
+        ]),
+        array.to_vec(&tree)
+    );
+    // removed objects are orphaned, so they can be placed somewhere else
+    assert_eq!(
+        Ok(value::Parent::None),
+        value::ObjectRef(MyId(22)).parent(&tree)
+    );
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "moved".to_string(),
+        item: Value::Object(value::ObjectRef(MyId(22))),
+    })
+    .unwrap();
+    assert_eq!(
+        Err(TreeError::InvalidRange),
+        tree.update(&Edit::ArrayDeleteRange {
+            ids: IdRange::from_ids(vec![MyId(30), MyId(3099)]),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::ArrayDeleteRange {
+            ids: IdRange::from_ids(vec![MyId(3099), MyId(30)]),
+        })
+    );
+}
+
+#[test]
+fn concurrent_inserts_survive_range_deletes() {
+    let (mut doc_a, mut doc_b, string) = replicas(|tx| {
+        let string = tx.create_string()?;
+        tx.set(
+            &value::ObjectRef(ROOT_ID),
+            "text",
+            Value::String(string.clone()),
+        )?;
+        tx.insert_text(&string, 0, "abcdef")?;
+        Ok(string)
+    });
+
+    // b's inserts have timestamps on either side of a's delete, and both survive it
+    let unrelated = doc_a
+        .transact(|tx| tx.set(&value::ObjectRef(ROOT_ID), "other", Value::Null))
+        .unwrap();
+    let delete = doc_a.transact(|tx| tx.delete_text(&string, 1, 4)).unwrap();
+    let lower = doc_b
+        .transact(|tx| tx.insert_text(&string, 2, "X"))
+        .unwrap();
+    let higher = doc_b
+        .transact(|tx| tx.insert_text(&string, 4, "Y"))
+        .unwrap();
+    assert!(lower.timestamp < delete.timestamp);
+    assert!(delete.timestamp < higher.timestamp);
+
+    doc_a.update_from_iter(vec![lower, higher].into_iter());
+    doc_b.update_from_iter(vec![unrelated, delete].into_iter());
+    for doc in &[&doc_a, &doc_b] {
+        assert_eq!(string.to_string(doc.tree()), Ok("aXYf".to_string()));
+    }
+}
+
+#[test]
+fn array_moves() {
+    let mut tree = Tree::new_with_array_root(MyId(0));
+    let root = value::ArrayRef(MyId(0));
+    let inner = value::ArrayRef(MyId(1));
+    tree.update(&Edit::ArrayCreate { id: inner.clone() })
+        .unwrap();
+    tree.update(&Edit::ArrayInsert {
+        index: value::ArrayIndex(MyId(0)),
+        id: value::ArrayIndex(MyId(2)),
+        item: Value::Array(inner.clone()),
+    })
+    .unwrap();
+    for i in 0..3 {
+        tree.update(&Edit::ArrayInsert {
+            index: value::ArrayIndex(MyId(if i == 0 { 2 } else { 9 + i })),
+            id: value::ArrayIndex(MyId(10 + i)),
+            item: Value::Int(i as i64),
+        })
+        .unwrap();
+    }
+    // [[], 0, 1, 2]
+
+    // move a primitive into the inner array, then move it again by its original id
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(11)),
+        index: value::ArrayIndex(MyId(1)),
+        new_id: value::ArrayIndex(MyId(20)),
+    })
+    .unwrap();
+    assert_eq!(Ok(vec![1]), inner.to_vec(&tree).map(vals_to_nums));
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(11)),
+        index: value::ArrayIndex(MyId(12)),
+        new_id: value::ArrayIndex(MyId(21)),
+    })
+    .unwrap();
+    assert_eq!(Ok(0), inner.len(&tree));
+    assert_eq!(Ok(Value::Int(1)), root.get(&tree, 3));
+    assert_eq!(
+        Ok(Some(3)),
+        root.id_to_index(&tree, &value::ArrayIndex(MyId(21)))
+    );
+
+    // deletes of a moved entry follow it to its new position, and drop the forwarding
+    tree.update(&Edit::ArrayDelete {
+        id: value::ArrayIndex(MyId(20)),
+    })
+    .unwrap();
+    assert_eq!(Ok(3), root.len(&tree));
+    assert!(tree.moves.is_empty());
+    // moving a deleted entry leaves a tombstone that can still be inserted after
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(11)),
+        index: value::ArrayIndex(MyId(1)),
+        new_id: value::ArrayIndex(MyId(22)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayInsert {
+        index: value::ArrayIndex(MyId(22)),
+        id: value::ArrayIndex(MyId(23)),
+        item: Value::Int(5),
+    })
+    .unwrap();
+    assert_eq!(Ok(vec![5]), inner.to_vec(&tree).map(vals_to_nums));
+
+    // collections are reparented when they move
+    tree.update(&Edit::ArrayCreate {
+        id: value::ArrayRef(MyId(3)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayInsert {
+        index: value::ArrayIndex(MyId(0)),
+        id: value::ArrayIndex(MyId(4)),
+        item: Value::Array(value::ArrayRef(MyId(3))),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(4)),
+        index: value::ArrayIndex(MyId(23)),
+        new_id: value::ArrayIndex(MyId(24)),
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(value::Parent::Array(inner.clone())),
+        value::ArrayRef(MyId(3)).parent(&tree)
+    );
+    assert_eq!(Ok(3), root.len(&tree));
+
+    // invalid moves leave the tree unchanged
+    assert_eq!(
+        Err(TreeError::EditWouldCauseCycle),
+        tree.update(&Edit::ArrayMove {
+            id: value::ArrayIndex(MyId(2)),
+            index: value::ArrayIndex(MyId(3)),
+            new_id: value::ArrayIndex(MyId(25)),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::ArrayMove {
+            id: value::ArrayIndex(MyId(10)),
+            index: value::ArrayIndex(MyId(0)),
+            new_id: value::ArrayIndex(MyId(12)),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::ArrayMove {
+            id: value::ArrayIndex(MyId(0)),
+            index: value::ArrayIndex(MyId(10)),
+            new_id: value::ArrayIndex(MyId(25)),
+        })
+    );
+    assert_eq!(Ok(3), root.len(&tree));
+    assert_eq!(Ok(Value::Array(inner.clone())), root.get(&tree, 0));
+    assert_eq!(Err(TreeError::UnknownId), tree.get_type(MyId(25)));
+
+    // forwarding into an array is dropped when the array is deleted, and forwarding through it
+    // skips over it
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(10)),
+        index: value::ArrayIndex(MyId(3)),
+        new_id: value::ArrayIndex(MyId(26)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(12)),
+        index: value::ArrayIndex(MyId(3)),
+        new_id: value::ArrayIndex(MyId(27)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayMove {
+        id: value::ArrayIndex(MyId(12)),
+        index: value::ArrayIndex(MyId(1)),
+        new_id: value::ArrayIndex(MyId(28)),
+    })
+    .unwrap();
+    tree.update(&Edit::ArrayDeleteRange {
+        ids: vec![IdRange {
+            start: MyId(24),
+            len: 1,
+        }],
+    })
+    .unwrap();
+    assert_eq!(3, tree.moves.len());
+    tree.delete_orphans();
+    assert_eq!(1, tree.moves.len());
+    tree.update(&Edit::ArrayDelete {
+        id: value::ArrayIndex(MyId(12)),
+    })
+    .unwrap();
+    assert!(tree.moves.is_empty());
+    assert_eq!(Ok(vec![5]), inner.to_vec(&tree).map(vals_to_nums));
+}
+
+#[test]
+fn concurrent_moves_converge() {
+    let (mut doc_a, mut doc_b, array) = replicas(create_list);
+    let op_a = doc_a
+        .transact(|tx| tx.move_entry(&array, 0, &array, 3).map(|_| ()))
+        .unwrap();
+    let op_b = doc_b
+        .transact(|tx| tx.move_entry(&array, 0, &array, 1).map(|_| ()))
+        .unwrap();
+    doc_a.update(op_b);
+    doc_b.update(op_a.clone());
+    // the ops have the same counter, so site 2's move is applied last and wins
+    let expected = vec![Value::Int(1), Value::Int(0), Value::Int(2), Value::Int(3)];
+    assert_eq!(array.to_vec(doc_a.tree()), Ok(expected.clone()));
+    assert_eq!(array.to_vec(doc_b.tree()), Ok(expected));
+
+    // a later move of the entry's original id still finds it
+    let original = match &op_a.edits[0] {
+        Edit::ArrayMove { id, .. } => id.clone(),
+        e => panic!("expected move, got {:?}", e),
+    };
+    let op = crate::DocOp {
+        timestamp: doc_a.next_timestamp(),
+        edits: vec![Edit::ArrayMove {
+            id: original,
+            index: value::ArrayIndex(array.0.clone()),
+            new_id: value::ArrayIndex(doc_a.next_id()),
+        }],
+    };
+    doc_a.update(op.clone());
+    doc_b.update(op);
+    assert_eq!(array.to_vec(doc_b.tree()), array.to_vec(doc_a.tree()));
+    assert_eq!(array.get(doc_b.tree(), 0), Ok(Value::Int(0)));
+    assert_eq!(array.len(doc_b.tree()), Ok(4));
+}
+
+#[test]
+fn concurrent_deletes_win_over_moves() {
+    for delete_on_a in &[true, false] {
+        let (mut doc_a, mut doc_b, array) = replicas(create_list);
+        let (deleter, mover) = if *delete_on_a {
+            (&mut doc_a, &mut doc_b)
+        } else {
+            (&mut doc_b, &mut doc_a)
+        };
+        let delete = deleter.transact(|tx| tx.remove(&array, 1)).unwrap();
+        let mov = mover
+            .transact(|tx| tx.move_entry(&array, 1, &array, 3).map(|_| ()))
+            .unwrap();
+        deleter.update(mov);
+        mover.update(delete);
+        let expected = vec![Value::Int(0), Value::Int(2), Value::Int(3)];
+        assert_eq!(array.to_vec(doc_a.tree()), Ok(expected.clone()));
+        assert_eq!(array.to_vec(doc_b.tree()), Ok(expected));
+    }
+}
+
+#[test]
+fn multi_value_registers() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let root = value::ObjectRef(MyId(0));
+    for i in 1..4 {
+        tree.update(&Edit::MapCreate {
+            id: value::ObjectRef(MyId(i)),
+        })
+        .unwrap();
+    }
+    tree.update(&Edit::MapInsert {
+        parent: root.clone(),
+        key: "key".to_string(),
+        item: Value::Object(value::ObjectRef(MyId(1))),
+    })
+    .unwrap();
+
+    // a multi-value write replaces a plain value, even without superseding it
+    for (write, object) in &[(10, 2), (11, 3)] {
+        tree.update(&Edit::MapInsertMulti {
+            parent: root.clone(),
+            key: "key".to_string(),
+            id: MyId(*write),
+            item: Value::Object(value::ObjectRef(MyId(*object))),
+            supersedes: vec![],
+        })
+        .unwrap();
+    }
+    assert_eq!(
+        Ok(vec![
+            Value::Object(value::ObjectRef(MyId(2))),
+            Value::Object(value::ObjectRef(MyId(3))),
+        ]),
+        root.get_all(&tree, "key")
+    );
+    assert_eq!(
+        Ok(Value::Object(value::ObjectRef(MyId(3)))),
+        root.get(&tree, "key")
+    );
+    assert_eq!(
+        Ok(value::Parent::None),
+        value::ObjectRef(MyId(1)).parent(&tree)
+    );
+    assert_eq!(
+        Ok(value::Parent::Object(root.clone())),
+        value::ObjectRef(MyId(2)).parent(&tree)
+    );
+    assert_eq!(Ok(vec!["key".to_string()]), root.keys(&tree));
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::MapInsertMulti {
+            parent: root.clone(),
+            key: "key".to_string(),
+            id: MyId(10),
+            item: Value::Null,
+            supersedes: vec![],
+        })
+    );
+
+    // superseding the last value leaves the earlier one visible
+    tree.update(&Edit::MapInsertMulti {
+        parent: root.clone(),
+        key: "key".to_string(),
+        id: MyId(12),
+        item: Value::Unset,
+        supersedes: vec![MyId(11)],
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(Value::Object(value::ObjectRef(MyId(2)))),
+        root.get(&tree, "key")
+    );
+    assert_eq!(
+        Ok(value::Parent::None),
+        value::ObjectRef(MyId(3)).parent(&tree)
+    );
+    tree.delete_orphans();
+    assert_eq!(Err(TreeError::UnknownId), tree.get_type(MyId(3)));
+
+    // nested objects in registers are cleaned up along with their parent
+    tree.update(&Edit::MapInsertMulti {
+        parent: value::ObjectRef(MyId(2)),
+        key: "nested".to_string(),
+        id: MyId(13),
+        item: Value::Null,
+        supersedes: vec![],
+    })
+    .unwrap();
+    tree.update(&Edit::MapInsert {
+        parent: root.clone(),
+        key: "key".to_string(),
+        item: Value::Unset,
+    })
+    .unwrap();
+    assert_eq!(Ok(vec![]), root.get_all(&tree, "key"));
+    tree.delete_orphans();
+    assert_eq!(Err(TreeError::UnknownId), tree.get_type(MyId(2)));
+}
+
+#[test]
+fn concurrent_multi_value_writes_are_kept() {
+    let (mut doc_a, mut doc_b, ()) = replicas(|_| Ok(()));
+    let root = value::ObjectRef(ROOT_ID);
+    let op_a = doc_a
+        .transact(|tx| tx.set_multi(&root, "key", Value::Int(1)))
+        .unwrap();
+    let op_b = doc_b
+        .transact(|tx| tx.set_multi(&root, "key", Value::Int(2)))
+        .unwrap();
+    doc_a.update(op_b);
+    doc_b.update(op_a);
+    for doc in &[&doc_a, &doc_b] {
+        assert_eq!(
+            root.get_all(doc.tree(), "key"),
+            Ok(vec![Value::Int(1), Value::Int(2)])
+        );
+        assert_eq!(root.get(doc.tree(), "key"), Ok(Value::Int(2)));
+    }
+
+    // a write that has seen both values resolves the conflict, while a concurrent write from the
+    // other replica is kept alongside it
+    let resolve = doc_a
+        .transact(|tx| tx.set_multi(&root, "key", Value::Int(3)))
+        .unwrap();
+    let concurrent = doc_b
+        .transact(|tx| tx.set_multi(&root, "key", Value::Null))
+        .unwrap();
+    doc_a.update(concurrent);
+    doc_b.update(resolve);
+    for doc in &[&doc_a, &doc_b] {
+        assert_eq!(
+            root.get_all(doc.tree(), "key"),
+            Ok(vec![Value::Int(3), Value::Null])
+        );
+    }
+
+    // a plain `set` replaces every value
+    doc_a
+        .transact(|tx| tx.set(&root, "key", Value::True))
+        .unwrap();
+    assert_eq!(root.get_all(doc_a.tree(), "key"), Ok(vec![Value::True]));
+    doc_a
+        .transact(|tx| tx.set_multi(&root, "key", Value::Unset))
+        .unwrap();
+    assert_eq!(root.get_all(doc_a.tree(), "key"), Ok(vec![]));
+    assert_eq!(root.contains_key(doc_a.tree(), "key"), Ok(false));
+}
+
+#[test]
+fn float_values() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let root = value::ObjectRef(MyId(0));
+    let weird_nan = f64::from_bits(f64::NAN.to_bits() ^ 1);
+    assert!(weird_nan.is_nan());
+    for (key, float) in &[
+        ("pi", 3.25),
+        ("nan", weird_nan),
+        ("neg", -0.0),
+        ("inf", f64::INFINITY),
+    ] {
+        tree.update(&Edit::MapInsert {
+            parent: root.clone(),
+            key: key.to_string(),
+            item: Value::Float(*float),
+        })
+        .unwrap();
+    }
+    assert_eq!(Ok(Value::Float(3.25)), root.get(&tree, "pi"));
+    // every NaN is stored and compared as the same value
+    assert_eq!(Ok(Value::Float(f64::NAN)), root.get(&tree, "nan"));
+    match root.get(&tree, "nan") {
+        Ok(Value::Float(f)) => assert_eq!(f64::NAN.to_bits(), f.to_bits()),
+        v => panic!("expected float, got {:?}", v),
+    }
+    assert_ne!(Value::<MyId>::Float(0.0), Value::Float(-0.0));
+    assert_eq!(Ok(Value::Float(-0.0)), root.get(&tree, "neg"));
+    assert_ne!(Value::<MyId>::Float(1.0), Value::Int(1));
+    // JSON has no NaN or infinity
+    assert_eq!(
+        Ok(r#"{"inf":null,"nan":null,"neg":-0.0,"pi":3.25}"#.to_string()),
+        tree.to_json_string(&tree.root_value())
+    );
+}
+
+#[test]
+fn bytes_values() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let root = value::ObjectRef(MyId(0));
+    for (key, bytes) in &[
+        ("a", &b""[..]),
+        ("b", &b"h"[..]),
+        ("c", &b"hi"[..]),
+        ("d", &[0xff, 0, 0x10, 1][..]),
+        ("e", &b"hi!"[..]),
+    ] {
+        tree.update(&Edit::MapInsert {
+            parent: root.clone(),
+            key: key.to_string(),
+            item: Value::Bytes(bytes.to_vec()),
+        })
+        .unwrap();
+    }
+    assert_eq!(Ok(Value::Bytes(b"hi!".to_vec())), root.get(&tree, "e"));
+    // bytes are replaced as a whole
+    tree.update(&Edit::MapInsert {
+        parent: root.clone(),
+        key: "e".to_string(),
+        item: Value::Bytes(b"hey".to_vec()),
+    })
+    .unwrap();
+    assert_eq!(Ok(Value::Bytes(b"hey".to_vec())), root.get(&tree, "e"));
+    assert_ne!(
+        Value::<MyId>::Bytes(b"hey".to_vec()),
+        Value::Bytes(b"he".to_vec())
+    );
+
+    assert_eq!(
+        Ok(r#"{"a":"","b":"aA==","c":"aGk=","d":"/wAQAQ==","e":"aGV5"}"#.to_string()),
+        tree.to_json_string(&tree.root_value())
+    );
+    assert_eq!(
+        Ok(serde_json::json!({"a": "", "b": "68", "c": "6869", "d": "ff001001", "e": "686579"})),
+        tree.to_json_with(&tree.root_value(), BytesEncoding::Hex)
+    );
+    assert_eq!(
+        Ok(serde_json::json!([255, 0, 16, 1])),
+        tree.to_json_with(&Value::Bytes(vec![0xff, 0, 0x10, 1]), BytesEncoding::Array)
+    );
+}
+
+#[test]
+fn counters() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let counter = value::CounterRef(MyId(1));
+    tree.update(&Edit::CounterCreate {
+        id: counter.clone(),
+    })
+    .unwrap();
+    assert_eq!(Ok(NodeType::Counter), tree.get_type(MyId(1)));
+    assert_eq!(Ok(0), counter.value(&tree));
+    assert_eq!(Ok(value::Parent::None), counter.parent(&tree));
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "count".to_string(),
+        item: Value::Counter(counter.clone()),
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(value::Parent::Object(value::ObjectRef(MyId(0)))),
+        counter.parent(&tree)
+    );
+
+    for amount in &[5, -3, i64::MAX, 2] {
+        tree.update(&Edit::CounterAdd {
+            id: counter.clone(),
+            amount: *amount,
+        })
+        .unwrap();
+    }
+    // additions wrap around rather than overflowing
+    assert_eq!(Ok(i64::MIN + 3), counter.value(&tree));
+    assert_eq!(
+        Ok(Value::Counter(counter.clone())),
+        value::ObjectRef(MyId(0)).get(&tree, "count")
+    );
+    assert_eq!(
+        Ok(format!(r#"{{"count":{}}}"#, i64::MIN + 3)),
+        tree.to_json_string(&tree.root_value())
+    );
+
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::CounterAdd {
+            id: value::CounterRef(MyId(0)),
+            amount: 1,
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        value::CounterRef(MyId(0)).value(&tree)
+    );
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::CounterCreate {
+            id: counter.clone(),
+        })
+    );
+
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "count".to_string(),
+        item: Value::Unset,
+    })
+    .unwrap();
+    tree.delete_orphans();
+    assert_eq!(Err(TreeError::UnknownId), counter.value(&tree));
+}
+
+#[test]
+fn concurrent_increments_are_summed() {
+    let (mut doc_a, mut doc_b, counter) = replicas(|tx| {
+        let counter = tx.create_counter()?;
+        tx.set(
+            &value::ObjectRef(ROOT_ID),
+            "views",
+            Value::Counter(counter.clone()),
+        )?;
+        tx.increment(&counter, 10)?;
+        Ok(counter)
+    });
+    let op_a = doc_a.transact(|tx| tx.increment(&counter, 1)).unwrap();
+    let op_b = doc_b
+        .transact(|tx| {
+            tx.increment(&counter, 5)?;
+            tx.increment(&counter, -2)
+        })
+        .unwrap();
+    doc_a.update(op_b);
+    doc_b.update(op_a);
+    for doc in &[&doc_a, &doc_b] {
+        assert_eq!(counter.value(doc.tree()), Ok(14));
+        assert_eq!(doc.to_json(), serde_json::json!({ "views": 14 }));
+    }
+}
+
+#[test]
+fn sets() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let set = value::SetRef(MyId(1));
+    tree.update(&Edit::SetCreate { id: set.clone() }).unwrap();
+    assert_eq!(Ok(NodeType::Set), tree.get_type(MyId(1)));
+    assert_eq!(Ok(true), set.is_empty(&tree));
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "set".to_string(),
+        item: Value::Set(set.clone()),
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(value::Parent::Object(value::ObjectRef(MyId(0)))),
+        set.parent(&tree)
+    );
+
+    let items = vec![
+        Value::Float(0.5),
+        Value::Int(7),
+        Value::True,
+        Value::Int(-7),
+        Value::Null,
+        Value::Float(f64::NAN),
+        Value::False,
+        Value::Int(7),
+    ];
+    for (i, item) in items.into_iter().enumerate() {
+        tree.update(&Edit::SetAdd {
+            id: set.clone(),
+            tag: MyId(10 + i),
+            item,
+        })
+        .unwrap();
+    }
+    assert_eq!(Ok(7), set.len(&tree));
+    assert_eq!(
+        vec![
+            Value::Null,
+            Value::False,
+            Value::True,
+            Value::Int(-7),
+            Value::Int(7),
+            Value::Float(0.5),
+            Value::Float(f64::NAN),
+        ],
+        set.iter(&tree).unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Ok(r#"{"set":[null,false,true,-7,7,0.5,null]}"#.to_string()),
+        tree.to_json_string(&tree.root_value())
+    );
+    assert_eq!(Ok(true), set.contains(&tree, &Value::Float(-f64::NAN)));
+    assert_eq!(Ok(false), set.contains(&tree, &Value::Int(8)));
+    assert_eq!(
+        Ok(vec![MyId(11), MyId(17)]),
+        tree.set_tags(&MyId(1), &Value::Int(7))
+    );
+
+    // 7 was added twice, so removing only one of the adds leaves it in the set
+    tree.update(&Edit::SetRemove {
+        id: set.clone(),
+        item: Value::Int(7),
+        observed: vec![MyId(11)],
+    })
+    .unwrap();
+    assert_eq!(Ok(true), set.contains(&tree, &Value::Int(7)));
+    tree.update(&Edit::SetRemove {
+        id: set.clone(),
+        item: Value::Int(7),
+        observed: vec![MyId(17)],
+    })
+    .unwrap();
+    assert_eq!(Ok(false), set.contains(&tree, &Value::Int(7)));
+    assert_eq!(Ok(6), set.len(&tree));
+    // removing a value that isn't there does nothing
+    tree.update(&Edit::SetRemove {
+        id: set.clone(),
+        item: Value::Int(7),
+        observed: vec![MyId(17)],
+    })
+    .unwrap();
+
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::SetAdd {
+            id: set.clone(),
+            tag: MyId(10),
+            item: Value::Float(0.5),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnsupportedValue),
+        tree.update(&Edit::SetAdd {
+            id: set.clone(),
+            tag: MyId(20),
+            item: Value::Object(value::ObjectRef(MyId(0))),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnsupportedValue),
+        set.contains(&tree, &Value::Unset)
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::SetAdd {
+            id: value::SetRef(MyId(0)),
+            tag: MyId(20),
+            item: Value::Null,
+        })
+    );
+
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "set".to_string(),
+        item: Value::Unset,
+    })
+    .unwrap();
+    tree.delete_orphans();
+    assert_eq!(Err(TreeError::UnknownId), set.len(&tree));
+}
+
+#[test]
+fn concurrent_adds_win_over_removes() {
+    let (mut doc_a, mut doc_b, set) = replicas(|tx| {
+        let set = tx.create_set()?;
+        tx.set(&value::ObjectRef(ROOT_ID), "tags", Value::Set(set.clone()))?;
+        tx.add_member(&set, Value::Int(1))?;
+        tx.add_member(&set, Value::Int(2))?;
+        Ok(set)
+    });
+    let op_a = doc_a
+        .transact(|tx| {
+            tx.remove_member(&set, &Value::Int(1))?;
+            tx.remove_member(&set, &Value::Int(2))
+        })
+        .unwrap();
+    let op_b = doc_b
+        .transact(|tx| {
+            tx.add_member(&set, Value::Int(1))?;
+            tx.add_member(&set, Value::Int(3))
+        })
+        .unwrap();
+    doc_a.update(op_b);
+    doc_b.update(op_a);
+    for doc in &[&doc_a, &doc_b] {
+        assert_eq!(
+            set.iter(doc.tree()).unwrap().collect::<Vec<_>>(),
+            vec![Value::Int(1), Value::Int(3)]
+        );
+        assert_eq!(doc.to_json(), serde_json::json!({ "tags": [1, 3] }));
+    }
+
+    // a remove that has seen every add does remove the value
+    let op = doc_a
+        .transact(|tx| tx.remove_member(&set, &Value::Int(1)))
+        .unwrap();
+    doc_b.update(op);
+    assert_eq!(doc_b.to_json(), serde_json::json!({ "tags": [3] }));
+}
+
+#[test]
+fn text_marks() {
+    fn span(text: &str, attributes: &[(&str, serde_json::Value)]) -> Span {
+        Span {
+            text: text.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+    fn mark(
+        start: usize,
+        end: usize,
+        expand: Expand,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Edit<MyId> {
+        Edit::TextMark {
+            start: value::StringIndex(MyId(start)),
+            end: value::StringIndex(MyId(end)),
+            expand,
+            name: name.to_string(),
+            value,
+        }
+    }
+    let bold = |b: bool| ("bold", serde_json::Value::Bool(b));
+    let link = || ("link", serde_json::json!({ "href": "https://example.com" }));
+
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(100)),
+        text: "hello world".to_string(),
+    })
+    .unwrap();
+    // "hello" expands after, so its end is anchored on the space after it
+    tree.update(&mark(100, 105, Expand::After, "bold", true.into()))
+        .unwrap();
+    tree.update(&mark(106, 110, Expand::None, "link", link().1))
+        .unwrap();
+    assert_eq!(
+        Ok(vec![
+            span("hello", &[bold(true)]),
+            span(" ", &[]),
+            span("world", &[link()]),
+        ]),
+        string.spans(&tree)
+    );
+
+    for (index, id, character) in &[(104, 1, '!'), (110, 2, '?'), (105, 3, '_')] {
+        tree.update(&Edit::TextInsert {
+            index: value::StringIndex(MyId(*index)),
+            id: value::StringIndex(MyId(*id)),
+            character: *character,
+        })
+        .unwrap();
+    }
+    assert_eq!(
+        Ok(vec![
+            span("hello!", &[bold(true)]),
+            span(" _", &[]),
+            span("world", &[link()]),
+            span("?", &[]),
+        ]),
+        string.spans(&tree)
+    );
+
+    // later marks win where they overlap, and null removes an attribute
+    tree.update(&mark(101, 102, Expand::None, "bold", false.into()))
+        .unwrap();
+    tree.update(&mark(
+        108,
+        110,
+        Expand::None,
+        "link",
+        serde_json::Value::Null,
+    ))
+    .unwrap();
+    // marks stay anchored on deleted characters
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(104)),
+    })
+    .unwrap();
+    assert_eq!(
+        Ok(vec![
+            span("h", &[bold(true)]),
+            span("el", &[bold(false)]),
+            span("l!", &[bold(true)]),
+            span(" _", &[]),
+            span("wo", &[link()]),
+            span("rld?", &[]),
+        ]),
+        string.spans(&tree)
+    );
+
+    // the string's own id anchors the start and end of the string
+    tree.update(&mark(0, 0, Expand::Both, "font", "serif".into()))
+        .unwrap();
+    tree.update(&Edit::TextInsert {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(4)),
+        character: '>',
+    })
+    .unwrap();
+    let font = || ("font", serde_json::Value::from("serif"));
+    assert_eq!(
+        Ok(span(">", &[font()])),
+        string.spans(&tree).map(|spans| spans[0].clone())
+    );
+    assert_eq!(
+        Ok(span("rld?", &[font()])),
+        string.spans(&tree).map(|spans| spans[6].clone())
+    );
+
+    assert_eq!(
+        Err(TreeError::InvalidRange),
+        tree.update(&mark(110, 100, Expand::None, "bold", true.into()))
+    );
+    tree.update(&Edit::TextCreate {
+        id: value::StringRef(MyId(50)),
+    })
+    .unwrap();
+    assert_eq!(
+        Err(TreeError::InvalidRange),
+        tree.update(&mark(100, 50, Expand::After, "bold", true.into()))
+    );
+    assert_eq!(
+        Err(TreeError::UnknownId),
+        tree.update(&mark(100, 999, Expand::None, "bold", true.into()))
+    );
+    assert_eq!(Ok(vec![]), value::StringRef(MyId(50)).spans(&tree));
+}
+
+#[test]
+fn text_marks_are_compacted() {
+    fn mark(start: usize, end: usize, expand: Expand, name: &str, value: bool) -> Edit<MyId> {
+        Edit::TextMark {
+            start: value::StringIndex(MyId(start)),
+            end: value::StringIndex(MyId(end)),
+            expand,
+            name: name.to_string(),
+            value: if value {
+                serde_json::Value::Bool(true)
+            } else {
+                serde_json::Value::Null
+            },
+        }
+    }
+    fn mark_count(tree: &Tree<MyId>) -> usize {
+        match &tree.nodes[&tree.id_to_node(&MyId(0)).unwrap()].data {
+            NodeData::String { marks, .. } => marks.len(),
+            _ => panic!("root was not a string"),
+        }
+    }
+
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(100)),
+        text: "abcdef".to_string(),
+    })
+    .unwrap();
+    tree.update(&mark(101, 104, Expand::After, "bold", true))
+        .unwrap();
+    tree.update(&mark(102, 102, Expand::None, "italic", true))
+        .unwrap();
+    // text typed after "d" would still get the first mark, so it isn't covered
+    tree.update(&mark(101, 103, Expand::None, "bold", true))
+        .unwrap();
+    assert_eq!(3, mark_count(&tree));
+
+    // marks entirely inside a later one for the same attribute are dropped
+    tree.update(&mark(100, 104, Expand::None, "bold", true))
+        .unwrap();
+    assert_eq!(2, mark_count(&tree));
+    // and so are null marks that don't overlap anything they could remove
+    tree.update(&mark(105, 105, Expand::None, "italic", false))
+        .unwrap();
+    tree.update(&mark(100, 100, Expand::None, "underline", false))
+        .unwrap();
+    assert_eq!(2, mark_count(&tree));
+    tree.update(&mark(0, 0, Expand::Both, "bold", false))
+        .unwrap();
+    assert_eq!(1, mark_count(&tree));
+    assert_eq!(
+        Ok(vec![
+            Span {
+                text: "ab".to_string(),
+                attributes: Default::default(),
+            },
+            Span {
+                text: "c".to_string(),
+                attributes: vec![("italic".to_string(), serde_json::Value::Bool(true))]
+                    .into_iter()
+                    .collect(),
+            },
+            Span {
+                text: "def".to_string(),
+                attributes: Default::default(),
+            },
+        ]),
+        value::StringRef(MyId(0)).spans(&tree)
+    );
+}
+
+#[test]
+fn concurrent_marks_converge() {
+    let (mut doc_a, mut doc_b, string) = replicas(|tx| {
+        let string = tx.create_string()?;
+        tx.set(
+            &value::ObjectRef(ROOT_ID),
+            "text",
+            Value::String(string.clone()),
+        )?;
+        tx.insert_text(&string, 0, "hello world")?;
+        Ok(string)
+    });
+    let op_a = doc_a
+        .transact(|tx| {
+            tx.mark_text(&string, 0, 5, "bold", true.into(), Expand::After)?;
+            tx.insert_text(&string, 11, " again")
+        })
+        .unwrap();
+    let op_b = doc_b
+        .transact(|tx| {
+            tx.insert_text(&string, 5, "!")?;
+            tx.mark_text(&string, 7, 5, "italic", true.into(), Expand::Both)
+        })
+        .unwrap();
+    doc_a.update(op_b);
+    doc_b.update(op_a);
+    let span = |text: &str, attributes: &[&str]| Span {
+        text: text.to_string(),
+        attributes: attributes
+            .iter()
+            .map(|name| (name.to_string(), serde_json::Value::Bool(true)))
+            .collect(),
+    };
+    for doc in &[&doc_a, &doc_b] {
+        // each insert lands inside the other replica's mark, since both marks expand there
+        assert_eq!(
+            string.spans(doc.tree()).unwrap(),
+            vec![
+                span("hello!", &["bold"]),
+                span(" ", &[]),
+                span("world again", &["italic"]),
+            ]
+        );
+    }
+}
+
+#[test]
+fn text_embeds() {
+    let mut tree = Tree::new_with_object_root(MyId(0));
+    let string = value::StringRef(MyId(1));
+    let mention = value::ObjectRef(MyId(2));
+    tree.update(&Edit::TextCreate { id: string.clone() })
+        .unwrap();
+    tree.update(&Edit::MapInsert {
+        parent: value::ObjectRef(MyId(0)),
+        key: "text".to_string(),
+        item: Value::String(string.clone()),
+    })
+    .unwrap();
+    tree.update(&Edit::MapCreate {
+        id: mention.clone(),
+    })
+    .unwrap();
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(1)),
+        id: value::StringIndex(MyId(100)),
+        text: "ab".to_string(),
+    })
+    .unwrap();
+    tree.update(&Edit::TextInsertEmbed {
+        index: value::StringIndex(MyId(100)),
+        id: value::StringIndex(MyId(3)),
+        item: Value::Object(mention.clone()),
+    })
+    .unwrap();
+    tree.update(&Edit::TextInsertEmbed {
+        index: value::StringIndex(MyId(101)),
+        id: value::StringIndex(MyId(4)),
+        item: Value::Int(5),
+    })
+    .unwrap();
+    // a placeholder character typed as text isn't an embed
+    tree.update(&Edit::TextInsert {
+        index: value::StringIndex(MyId(4)),
+        id: value::StringIndex(MyId(5)),
+        character: value::EMBED_CHAR,
+    })
+    .unwrap();
+
+    assert_eq!(
+        Ok("a\u{fffc}b\u{fffc}\u{fffc}".to_string()),
+        string.to_string(&tree)
+    );
+    assert_eq!(Ok(5), string.len(&tree));
+    assert_eq!(
+        vec![
+            value::TextChunk::Text("a".to_string()),
+            value::TextChunk::Embed(value::StringIndex(MyId(3)), Value::Object(mention.clone())),
+            value::TextChunk::Text("b".to_string()),
+            value::TextChunk::Embed(value::StringIndex(MyId(4)), Value::Int(5)),
+            value::TextChunk::Text("\u{fffc}".to_string()),
+        ],
+        string.chunks(&tree).unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Ok(value::Parent::String(string.clone())),
+        mention.parent(&tree)
+    );
+    assert_eq!(
+        Ok(Some(Value::Int(5))),
+        value::StringIndex(MyId(4)).embedded(&tree)
+    );
+    assert_eq!(Ok(None), value::StringIndex(MyId(100)).embedded(&tree));
+    // exporting to JSON leaves the embedded values out, keeping only their placeholders
+    assert_eq!(
+        Ok(serde_json::json!("a\u{fffc}b\u{fffc}\u{fffc}")),
+        tree.to_json(&Value::String(string.clone()))
+    );
+
+    assert_eq!(
+        Err(TreeError::UnsupportedValue),
+        tree.update(&Edit::TextInsertEmbed {
+            index: value::StringIndex(MyId(1)),
+            id: value::StringIndex(MyId(6)),
+            item: Value::Unset,
+        })
+    );
+    assert_eq!(
+        Err(TreeError::EditWouldCauseCycle),
+        tree.update(&Edit::TextInsertEmbed {
+            index: value::StringIndex(MyId(1)),
+            id: value::StringIndex(MyId(6)),
+            item: Value::Object(value::ObjectRef(MyId(0))),
+        })
+    );
+    assert_eq!(
+        Err(TreeError::UnexpectedNodeType),
+        tree.update(&Edit::TextInsertEmbed {
+            index: value::StringIndex(MyId(0)),
+            id: value::StringIndex(MyId(6)),
+            item: Value::Null,
+        })
+    );
+    // a failed insert leaves the collection where it was
+    let other = value::ObjectRef(MyId(7));
+    tree.update(&Edit::MapCreate { id: other.clone() }).unwrap();
+    assert_eq!(
+        Err(TreeError::DuplicateId),
+        tree.update(&Edit::TextInsertEmbed {
+            index: value::StringIndex(MyId(1)),
+            id: value::StringIndex(MyId(100)),
+            item: Value::Object(other.clone()),
+        })
+    );
+    assert_eq!(Ok(value::Parent::None), other.parent(&tree));
+
+    // deleting a placeholder removes its embed, orphaning collections
+    tree.update(&Edit::TextDelete {
+        id: value::StringIndex(MyId(3)),
+    })
+    .unwrap();
+    assert_eq!(Ok(value::Parent::None), mention.parent(&tree));
+    assert_eq!(Ok(None), value::StringIndex(MyId(3)).embedded(&tree));
+    tree.delete_orphans();
+    assert_eq!(Err(TreeError::UnknownId), mention.len(&tree));
+    tree.update(&Edit::TextDeleteRange {
+        ids: IdRange::from_ids(vec![MyId(101), MyId(4)]),
+    })
+    .unwrap();
+    assert_eq!(Ok(None), value::StringIndex(MyId(4)).embedded(&tree));
+    assert_eq!(
+        vec![value::TextChunk::Text("a\u{fffc}".to_string())],
+        string.chunks(&tree).unwrap().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn text_embeds_follow_segment_splits() {
+    let mut tree = Tree::new_with_string_root(MyId(0));
+    let string = value::StringRef(MyId(0));
+    let mention = value::ObjectRef(MyId(1));
+    tree.update(&Edit::MapCreate {
+        id: mention.clone(),
+    })
+    .unwrap();
+    tree.update(&Edit::TextInsertEmbed {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(2)),
+        item: Value::Object(mention.clone()),
+    })
+    .unwrap();
+    // enough text before the embed to split its segment several times
+    let text = "x".repeat(3000);
+    tree.update(&Edit::TextInsertRun {
+        index: value::StringIndex(MyId(0)),
+        id: value::StringIndex(MyId(100)),
+        text: text.clone(),
+    })
+    .unwrap();
+    assert_eq!(
+        vec![
+            value::TextChunk::Text(text),
+            value::TextChunk::Embed(value::StringIndex(MyId(2)), Value::Object(mention.clone())),
+        ],
+        string.chunks(&tree).unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        Ok(Some(Value::Object(mention.clone()))),
+        value::StringIndex(MyId(2)).embedded(&tree)
+    );
+
+    // a range delete spanning several segments removes the embed
+    tree.update(&Edit::TextDeleteRange {
+        ids: IdRange::from_ids((1000..3100).chain(Some(2)).map(MyId)),
+    })
+    .unwrap();
+    assert_eq!(Ok(value::Parent::None), mention.parent(&tree));
+    assert_eq!(Ok(900), string.len(&tree));
+}
@@ -0,0 +1,122 @@
+use super::sequence;
+use super::tree::{NodeType, SequentialId, Tree, TreeError};
+use super::value::{StringIndex, StringRef};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// The unit a text offset is measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextUnit {
+    /// UTF-8 bytes, as used by Rust strings.
+    Utf8,
+    /// UTF-16 code units, as used by browsers and the language server protocol.
+    Utf16,
+    /// Unicode scalar values, as used by `StringRef::index_at` and `StringIndex::offset`.
+    Scalar,
+}
+
+impl TextUnit {
+    /// Returns the length of `character` in this unit.
+    pub fn len_of(self, character: char) -> usize {
+        match self {
+            TextUnit::Utf8 => character.len_utf8(),
+            TextUnit::Utf16 => character.len_utf16(),
+            TextUnit::Scalar => 1,
+        }
+    }
+}
+
+/// A zero-based line and column in a string. Lines are separated by `'\n'`, and the column is
+/// measured from the start of the line in some `TextUnit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> StringRef<Id> {
+    /// Returns the length of the string in `unit`.
+    pub fn len_in(&self, tree: &Tree<Id>, unit: TextUnit) -> Result<usize, TreeError> {
+        self.check_type(tree)?;
+        Ok(sequence::weight(tree, &self.0)?.get(unit))
+    }
+
+    /// Like `index_at`, but with `offset` measured in `unit`. Returns
+    /// `TreeError::NotCharBoundary` if `offset` falls inside a character. Takes `O(log n)`.
+    pub fn index_at_unit(
+        &self,
+        tree: &Tree<Id>,
+        offset: usize,
+        unit: TextUnit,
+    ) -> Result<StringIndex<Id>, TreeError> {
+        self.check_type(tree)?;
+        Ok(StringIndex(sequence::index_at(
+            tree, &self.0, offset, unit,
+        )?))
+    }
+
+    /// Returns the index of a cursor at `position`, with the column measured in `unit`. Returns
+    /// `TreeError::IndexOutOfBounds` if the line doesn't exist or the column is past the end of
+    /// the line. Takes `O(n)`.
+    pub fn index_at_line_column(
+        &self,
+        tree: &Tree<Id>,
+        position: LineColumn,
+        unit: TextUnit,
+    ) -> Result<StringIndex<Id>, TreeError> {
+        let mut index = StringIndex(self.0.clone());
+        let mut line = 0;
+        let mut column = 0;
+        for (id, character) in self.iter(tree)? {
+            if line == position.line {
+                if column == position.column {
+                    return Ok(index);
+                }
+                if column > position.column {
+                    return Err(TreeError::NotCharBoundary);
+                }
+                if character == '\n' {
+                    return Err(TreeError::IndexOutOfBounds);
+                }
+            }
+            if character == '\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += unit.len_of(character);
+            }
+            index = id;
+        }
+        match (line == position.line, column.cmp(&position.column)) {
+            (true, std::cmp::Ordering::Equal) => Ok(index),
+            (true, std::cmp::Ordering::Greater) => Err(TreeError::NotCharBoundary),
+            _ => Err(TreeError::IndexOutOfBounds),
+        }
+    }
+}
+
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> StringIndex<Id> {
+    /// Like `offset`, but measured in `unit`. Takes `O(log n)`.
+    pub fn offset_in(&self, tree: &Tree<Id>, unit: TextUnit) -> Result<usize, TreeError> {
+        match tree.get_type(self.0.clone())? {
+            NodeType::String | NodeType::Character => sequence::offset_of(tree, &self.0, unit),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns the line and column of a cursor placed at this index, with the column measured in
+    /// `unit`. Takes `O(n)`.
+    pub fn line_column(&self, tree: &Tree<Id>, unit: TextUnit) -> Result<LineColumn, TreeError> {
+        let chars = self.offset(tree)?;
+        let mut position = LineColumn { line: 0, column: 0 };
+        for (_, character) in self.parent(tree)?.iter(tree)?.take(chars) {
+            if character == '\n' {
+                position.line += 1;
+                position.column = 0;
+            } else {
+                position.column += unit.len_of(character);
+            }
+        }
+        Ok(position)
+    }
+}
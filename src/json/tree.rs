@@ -1,19 +1,29 @@
-use super::sequence;
+use super::counted::{SegmentIndex, Weight};
+use super::marks::{Expand, Mark};
+use super::sequence::{self, IdRun};
+use super::text::TextUnit;
 use super::value::{self, Value};
 use im::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::ops::Range;
+use std::sync::Arc;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(super) enum Child {
     True,
     False,
     Int(i64),
+    /// Bits of a float, as returned by `value::float_bits`.
+    Float(u64),
+    /// Shared so that cloning a segment or object doesn't copy the bytes.
+    Bytes(Arc<[u8]>),
     Null,
     Collection(NodeId),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Edit<Id> {
     ArrayCreate {
         /// id of new list
@@ -43,6 +53,71 @@ pub enum Edit<Id> {
         /// Item to be set. If this item had a prevous parent, it is removed from that parent.
         item: Value<Id>,
     },
+    /// Moves the entry `id` to directly after `index`, which may be in a different array. The
+    /// moved entry gets the id `new_id` at its new position, and a tombstone is left behind in
+    /// its old position. If the entry was already moved, the move applies to wherever it ended
+    /// up, so concurrent moves of the same entry leave it wherever the last one put it. If the
+    /// entry was deleted, `new_id` is created as a tombstone.
+    ArrayMove {
+        /// Id of the entry to move.
+        id: value::ArrayIndex<Id>,
+        /// Position to move the entry to.
+        index: value::ArrayIndex<Id>,
+        /// Id of the entry at its new position.
+        new_id: value::ArrayIndex<Id>,
+    },
+    /// Like `MapInsert`, but concurrent writes to the same key are all kept, rather than the last
+    /// one replacing the others. A write only replaces the values it lists in `supersedes`, so
+    /// writes that didn't know about each other leave the key with several values. A `MapInsert`
+    /// to the key replaces all of them.
+    MapInsertMulti {
+        /// Id of parent map
+        parent: value::ObjectRef<Id>,
+        /// Key of item in hashmap
+        key: String,
+        /// Id of this write, which later writes use to supersede it.
+        id: Id,
+        /// Item to be set. If this is `Value::Unset`, the superseded values are removed without
+        /// adding a new one.
+        item: Value<Id>,
+        /// Ids of the writes to this key that this write replaces.
+        supersedes: Vec<Id>,
+    },
+    CounterCreate {
+        /// id of new counter
+        id: value::CounterRef<Id>,
+    },
+    CounterAdd {
+        /// Id of counter to add to
+        id: value::CounterRef<Id>,
+        /// Amount to add, which may be negative. Additions wrap around on overflow, so that the
+        /// result doesn't depend on the order they're applied in.
+        amount: i64,
+    },
+    SetCreate {
+        /// id of new set
+        id: value::SetRef<Id>,
+    },
+    /// Adds `item` to a set. Adding a value that's already in the set gives it another tag.
+    SetAdd {
+        /// Id of set to add to
+        id: value::SetRef<Id>,
+        /// Id of this add, which a `SetRemove` lists in `observed` to remove it.
+        tag: Id,
+        /// Value to add. Sets can only contain `Null`, `True`, `False`, `Int`, `Float` and
+        /// `Bytes`.
+        item: Value<Id>,
+    },
+    /// Removes `item` from a set. Only the adds listed in `observed` are removed, so if `item` was
+    /// added concurrently with the remove it stays in the set.
+    SetRemove {
+        /// Id of set to remove from
+        id: value::SetRef<Id>,
+        /// Value to remove.
+        item: Value<Id>,
+        /// Tags of the adds of `item` that this remove has seen.
+        observed: Vec<Id>,
+    },
     TextCreate {
         /// id of new text
         id: value::StringRef<Id>,
@@ -59,6 +134,173 @@ pub enum Edit<Id> {
         /// Id of character to delete
         id: value::StringIndex<Id>,
     },
+    TextInsertRun {
+        /// Position to insert at.
+        index: value::StringIndex<Id>,
+        /// Id of the first newly created character. The character `n` places later in `text` has
+        /// id `id.successor(n)`.
+        id: value::StringIndex<Id>,
+        /// Characters to insert.
+        text: String,
+    },
+    /// Deletes the characters whose ids are in `ids`. Only the characters listed are deleted, so
+    /// characters inserted between them by edits the deleting replica hadn't seen survive.
+    TextDeleteRange {
+        /// Ids of the characters to delete, which must all be in the same string. Characters that
+        /// are already deleted are skipped.
+        ids: Vec<IdRange<Id>>,
+    },
+    /// Inserts `item` into a string as a single placeholder character, `value::EMBED_CHAR`, so it
+    /// takes up one place in the text. Like other characters, the placeholder is deleted with
+    /// `TextDelete` or `TextDeleteRange`.
+    TextInsertEmbed {
+        /// Position to insert at.
+        index: value::StringIndex<Id>,
+        /// Id of the placeholder character.
+        id: value::StringIndex<Id>,
+        /// Value to embed. Collections are moved into the string, like `ArrayInsert`.
+        item: Value<Id>,
+    },
+    /// Sets the formatting attribute `name` to `value` on a range of a string. Like
+    /// `TextDeleteRange`, the range is anchored on characters, which may be deleted. Where marks
+    /// for the same attribute overlap, the one applied last wins.
+    TextMark {
+        /// Id of the first character in the range. If `expand` includes `Before`, this is instead
+        /// the character just before the range, so text inserted directly before the range is
+        /// inside it. The string's own id stands for the start of the string.
+        start: value::StringIndex<Id>,
+        /// Id of the last character in the range. If `expand` includes `After`, this is instead
+        /// the character just after the range, so text inserted directly after the range is
+        /// inside it. The string's own id stands for the end of the string.
+        end: value::StringIndex<Id>,
+        /// Which edges of the range grow to include text inserted there.
+        expand: Expand,
+        /// Name of the attribute, such as `"bold"` or `"link"`.
+        name: String,
+        /// Value of the attribute. Null removes the attribute from the range.
+        value: serde_json::Value,
+    },
+    /// Deletes the entries whose ids are in `ids`. Like `TextDeleteRange`, entries inserted
+    /// between them by edits the deleting replica hadn't seen survive.
+    ArrayDeleteRange {
+        /// Ids of the entries to delete, which must all be in the same array. Entries that are
+        /// already deleted are skipped.
+        ids: Vec<IdRange<Id>>,
+    },
+}
+
+impl<Id> Edit<Id> {
+    /// Returns every id referenced by this edit. Runs are represented by their first id.
+    pub fn ids(&self) -> impl Iterator<Item = &Id> {
+        fn value_id<Id>(value: &Value<Id>) -> Option<&Id> {
+            match value {
+                Value::String(value::StringRef(id))
+                | Value::Array(value::ArrayRef(id))
+                | Value::Object(value::ObjectRef(id))
+                | Value::Counter(value::CounterRef(id))
+                | Value::Set(value::SetRef(id)) => Some(id),
+                Value::Int(_)
+                | Value::Float(_)
+                | Value::Bytes(_)
+                | Value::True
+                | Value::False
+                | Value::Null
+                | Value::Unset => None,
+            }
+        }
+        let ids = match self {
+            Edit::ArrayCreate { id } => vec![&id.0],
+            Edit::ArrayInsert { index, id, item } => {
+                let mut ids = vec![&index.0, &id.0];
+                ids.extend(value_id(item));
+                ids
+            }
+            Edit::ArrayDelete { id } => vec![&id.0],
+            Edit::ArrayMove { id, index, new_id } => vec![&id.0, &index.0, &new_id.0],
+            Edit::MapCreate { id } => vec![&id.0],
+            Edit::MapInsert { parent, item, .. } => {
+                let mut ids = vec![&parent.0];
+                ids.extend(value_id(item));
+                ids
+            }
+            Edit::MapInsertMulti {
+                parent,
+                id,
+                item,
+                supersedes,
+                ..
+            } => {
+                let mut ids = vec![&parent.0, id];
+                ids.extend(value_id(item));
+                ids.extend(supersedes);
+                ids
+            }
+            Edit::CounterCreate { id } => vec![&id.0],
+            Edit::CounterAdd { id, .. } => vec![&id.0],
+            Edit::SetCreate { id } => vec![&id.0],
+            Edit::SetAdd { id, tag, .. } => vec![&id.0, tag],
+            Edit::SetRemove { id, observed, .. } => {
+                let mut ids = vec![&id.0];
+                ids.extend(observed);
+                ids
+            }
+            Edit::TextCreate { id } => vec![&id.0],
+            Edit::TextInsert { index, id, .. } => vec![&index.0, &id.0],
+            Edit::TextDelete { id } => vec![&id.0],
+            Edit::TextInsertRun { index, id, .. } => vec![&index.0, &id.0],
+            Edit::TextDeleteRange { ids } => ids.iter().map(|run| &run.start).collect(),
+            Edit::TextMark { start, end, .. } => vec![&start.0, &end.0],
+            Edit::TextInsertEmbed { index, id, item } => {
+                let mut ids = vec![&index.0, &id.0];
+                ids.extend(value_id(item));
+                ids
+            }
+            Edit::ArrayDeleteRange { ids } => ids.iter().map(|run| &run.start).collect(),
+        };
+        ids.into_iter()
+    }
+}
+
+/// Ids that can be allocated in contiguous runs. A run of characters inserted with
+/// `Edit::TextInsertRun` only stores the id of its first character, and the rest are derived from
+/// it with `successor`.
+pub trait SequentialId: Sized {
+    /// Returns the id `n` places after this one in a run, or `None` if there is no such id.
+    fn checked_successor(&self, n: usize) -> Option<Self>;
+
+    /// Returns the id `n` places after this one in a run. Panics if there is no such id, which
+    /// can't happen for ids within a run the tree has accepted.
+    fn successor(&self, n: usize) -> Self {
+        self.checked_successor(n).expect("id run overflowed")
+    }
+
+    /// Returns `n` if this id is `start.successor(n)`.
+    fn steps_from(&self, start: &Self) -> Option<usize>;
+}
+
+/// A run of `len` consecutive ids, `start`, `start.successor(1)` and so on, used by edits that
+/// refer to many existing items at once.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdRange<Id> {
+    /// First id in the run.
+    pub start: Id,
+    /// Number of ids in the run.
+    pub len: usize,
+}
+
+impl<Id: SequentialId + PartialEq> IdRange<Id> {
+    /// Packs `ids` into runs, starting a new run wherever an id isn't the successor of the one
+    /// before it.
+    pub fn from_ids(ids: impl IntoIterator<Item = Id>) -> Vec<IdRange<Id>> {
+        let mut runs: Vec<IdRange<Id>> = Vec::new();
+        for id in ids {
+            match runs.last_mut() {
+                Some(run) if id.steps_from(&run.start) == Some(run.len) => run.len += 1,
+                _ => runs.push(IdRange { start: id, len: 1 }),
+            }
+        }
+        runs
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -68,36 +310,52 @@ pub enum NodeType {
     Object,
     Array,
     ArrayEntry,
+    Counter,
+    Set,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TreeError {
     UnknownId,
     UnexpectedNodeType,
     DuplicateId,
     NodeAlreadyHadParent,
     EditWouldCauseCycle,
+    IndexOutOfBounds,
+    /// The value can't be represented in the tree, such as a JSON integer too large for an `i64`,
+    /// or a collection added to a set.
+    UnsupportedValue,
+    /// A text offset fell inside a character, such as between the two UTF-16 code units of a
+    /// surrogate pair.
+    NotCharBoundary,
+    /// The end of a range came before its start, or the two ends were in different sequences.
+    InvalidRange,
+    /// A run of ids would extend past the largest id that can be represented.
+    IdOverflow,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
-pub(super) struct NodeId(usize);
+pub(super) struct NodeId(pub(super) usize);
 
 /// This struct is left public for others who would like to build their own CRDT library or have a
 /// custom setup of some kind. Most crudite users will not need to use this.
 ///
 /// A JSON-compatible document where each character and value in the document has a unique ID, and
 /// deletions in arrays and strings maintain tombstones for ordering future insertions. All methods
-/// on this tree should be `O(log n)` or better unless otherwise noted. The tree also internally
-/// uses persistent data structures, so cloning should be a very fast and efficient operation.
+/// on this tree should be `O(log n)` or better unless otherwise noted, though inserts that split a
+/// segment are `O(log n)` amortized rather than in the worst case. The tree also internally uses
+/// persistent data structures, so cloning should be a very fast and efficient operation.
 ///
 /// Sequences like arrays and strings in `Tree` are represented by a persistent double linked list
 /// of segments. This is sorta like just the leaves of a rope connected by a doubly linked list.
 /// Why not use a rope? Ropes are useful for calculating "what character is at position n" very
-/// efficiently. However, it's tricky to make ropes work with random access via IDs, and there is
-/// overhead for calculating the rope. We opt instead to make indexed access `O(n)` and ID-based
-/// access `O(1)`.
+/// efficiently. However, it's tricky to make ropes work with random access via IDs. Instead, ID
+/// based access goes straight to the segment through `id_to_node` in `O(1)`, and each sequence
+/// container keeps a persistent order-statistic tree over its segments, counting live items,
+/// UTF-8 bytes and UTF-16 code units. This makes indexed access `O(log n)`, plus a scan within a
+/// single segment, which is bounded in length.
 #[derive(Clone, Debug)]
-pub struct Tree<Id: Hash + Clone + Eq + Debug> {
+pub struct Tree<Id: Hash + Clone + Eq + Debug + SequentialId> {
     /// Number to use for the next node that is created.
     next_node: NodeId,
 
@@ -111,20 +369,44 @@ pub struct Tree<Id: Hash + Clone + Eq + Debug> {
     /// the string.
     pub(super) id_to_node: HashMap<Id, NodeId>,
 
+    /// Maps the ids of array entries that were moved away with `Edit::ArrayMove` to the id they
+    /// were moved to. Entries are dropped once the entry they lead to is deleted.
+    pub(super) moves: HashMap<Id, Id>,
+
+    /// The reverse of `moves`, from the id an entry was moved to back to the id it was moved from.
+    moved_from: HashMap<Id, Id>,
+
     /// Maps node ids to node data.
     pub(super) nodes: HashMap<NodeId, Node<Id>>,
 }
 
 #[derive(Clone, Debug)]
-pub(super) struct Node<Id: Hash + Clone + Eq + Debug> {
+pub(super) struct Node<Id: Hash + Clone + Eq + Debug + SequentialId> {
     pub(super) data: NodeData<Id>,
     pub(super) parent: Option<NodeId>,
 }
 
 #[derive(Clone, Debug)]
-pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug> {
+pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug + SequentialId> {
     Object {
         items: HashMap<String, Child>,
+        /// Every value of the keys last written with `Edit::MapInsertMulti`, along with the id of
+        /// the write that set it, in the order they were written. The last value of each key is
+        /// also stored in `items`.
+        registers: HashMap<String, Vec<(Id, Child)>>,
+        id: Id,
+    },
+    /// Represents a counter.
+    Counter {
+        /// Sum of every addition to the counter.
+        value: i64,
+        id: Id,
+    },
+    /// Represents a set of primitive values.
+    Set {
+        /// Maps each member of the set to the tags of the `Edit::SetAdd`s that added it and
+        /// haven't been removed yet. Members with no tags left are removed from the map.
+        members: HashMap<Child, Vec<Id>>,
         id: Id,
     },
     /// Represents a JSON array value.
@@ -135,6 +417,8 @@ pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug> {
         /// The last `ArraySegment` in the string value. May be equal to `start` if there is only
         /// one segment.
         end: NodeId,
+        /// Order-statistic index over the segments, for positional lookups.
+        segments: SegmentIndex,
         id: Id,
     },
     /// Represents a range of a JSON array value.
@@ -147,9 +431,8 @@ pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug> {
         next: NodeId,
         /// Children in this segment.
         contents: Vec<Child>,
-        /// List of ids. If they are a tombstone, the the Option will be None, if they represent a
-        /// live character, the Option will show the index of the character.
-        ids: Vec<(Id, Option<usize>)>,
+        /// Runs of ids of the items in this segment, including tombstones.
+        ids: Vec<IdRun<Id>>,
     },
     /// Represents a JSON string value.
     String {
@@ -159,6 +442,11 @@ pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug> {
         /// The last `StringSegment` in the string value. May be equal to `start` if there is only
         /// one segment.
         end: NodeId,
+        /// Order-statistic index over the segments, for positional lookups.
+        segments: SegmentIndex,
+        /// Formatting marks on the string, in the order they were applied. Shared so that text
+        /// edits, which copy the string node, don't copy the marks.
+        marks: Arc<Vec<Mark<Id>>>,
         id: Id,
     },
     /// Represents a range of a JSON string value.
@@ -171,16 +459,20 @@ pub(super) enum NodeData<Id: Hash + Clone + Eq + Debug> {
         next: NodeId,
         /// String contents of this segment.
         contents: String,
-        /// List of ids. If they are a tombstone, the the Option will be None, if they represent a
-        /// live character, the Option will show the index of the character.
-        ids: Vec<(Id, Option<usize>)>,
+        /// Values embedded in this segment, by the id of the live placeholder character holding
+        /// their place.
+        embeds: HashMap<Id, Child>,
+        /// Runs of ids of the items in this segment, including tombstones.
+        ids: Vec<IdRun<Id>>,
     },
 }
 
-impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> Node<Id> {
     fn id(&self) -> Option<Id> {
         match &self.data {
             NodeData::Object { id, .. } => Some(id.clone()),
+            NodeData::Counter { id, .. } => Some(id.clone()),
+            NodeData::Set { id, .. } => Some(id.clone()),
             NodeData::String { id, .. } => Some(id.clone()),
             NodeData::StringSegment { .. } => None,
             NodeData::Array { id, .. } => Some(id.clone()),
@@ -196,12 +488,14 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
                 prev: *prev,
                 next: *next,
                 contents: String::new(),
+                embeds: HashMap::new(),
                 ids: Vec::new(),
             },
             NodeData::String { end, start, .. } => NodeData::StringSegment {
                 prev: *end,
                 next: *start,
                 contents: String::new(),
+                embeds: HashMap::new(),
                 ids: Vec::new(),
             },
             NodeData::ArraySegment { prev, next, .. } => NodeData::ArraySegment {
@@ -243,7 +537,7 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
         }
     }
 
-    pub(super) fn segment_ids(&self) -> Result<&Vec<(Id, Option<usize>)>, TreeError> {
+    pub(super) fn segment_ids(&self) -> Result<&Vec<IdRun<Id>>, TreeError> {
         match &self.data {
             NodeData::StringSegment { ids, .. } => Ok(ids),
             NodeData::ArraySegment { ids, .. } => Ok(ids),
@@ -251,7 +545,7 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
         }
     }
 
-    pub(super) fn segment_ids_mut(&mut self) -> Result<&mut Vec<(Id, Option<usize>)>, TreeError> {
+    pub(super) fn segment_ids_mut(&mut self) -> Result<&mut Vec<IdRun<Id>>, TreeError> {
         match &mut self.data {
             NodeData::StringSegment { ids, .. } => Ok(ids),
             NodeData::ArraySegment { ids, .. } => Ok(ids),
@@ -267,31 +561,114 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
         }
     }
 
-    pub(super) fn segment_is_container(&self) -> bool {
+    /// Returns the segment index of a sequence container.
+    pub(super) fn container_segments(&self) -> &SegmentIndex {
+        match &self.data {
+            NodeData::String { segments, .. } => segments,
+            NodeData::Array { segments, .. } => segments,
+            _ => panic!("container_segments called on non-container node"),
+        }
+    }
+
+    pub(super) fn container_segments_mut(&mut self) -> &mut SegmentIndex {
+        match &mut self.data {
+            NodeData::String { segments, .. } => segments,
+            NodeData::Array { segments, .. } => segments,
+            _ => panic!("container_segments called on non-container node"),
+        }
+    }
+
+    /// Returns the weight of the live item at `index` in this segment's contents.
+    pub(super) fn segment_item_weight(&self, index: usize) -> Weight {
+        match &self.data {
+            NodeData::StringSegment { contents, .. } => Weight::of_char(
+                contents[index..]
+                    .chars()
+                    .next()
+                    .expect("segment id pointed past end of contents"),
+            ),
+            NodeData::ArraySegment { .. } => Weight::item(),
+            _ => panic!("segment_item_weight called on non-segment node"),
+        }
+    }
+
+    /// Returns the weight of the `len` bytes or entries of this segment's contents starting at
+    /// `index`.
+    pub(super) fn segment_range_weight(&self, index: usize, len: usize) -> Weight {
+        match &self.data {
+            NodeData::StringSegment { contents, .. } => {
+                Weight::of_str(&contents[index..index + len])
+            }
+            NodeData::ArraySegment { .. } => Weight {
+                items: len,
+                ..Weight::default()
+            },
+            _ => panic!("segment_range_weight called on non-segment node"),
+        }
+    }
+
+    /// Returns the contents index of the item `n` items after the one at contents index `index`.
+    pub(super) fn segment_contents_advance(&self, index: usize, n: usize) -> usize {
+        match &self.data {
+            NodeData::StringSegment { contents, .. } => contents[index..]
+                .char_indices()
+                .nth(n)
+                .map(|(offset, _)| index + offset)
+                .unwrap_or(contents.len()),
+            NodeData::ArraySegment { .. } => index + n,
+            _ => panic!("segment_contents_advance called on non-segment node"),
+        }
+    }
+
+    /// Returns the total weight of the live items in this segment. Takes `O(n)` in the length of
+    /// the segment.
+    pub(super) fn segment_weight(&self) -> Weight {
         match &self.data {
-            NodeData::String { .. } => true,
-            NodeData::Array { .. } => true,
-            _ => false,
+            NodeData::StringSegment { contents, .. } => Weight::of_str(contents),
+            NodeData::ArraySegment { contents, .. } => Weight {
+                items: contents.len(),
+                ..Weight::default()
+            },
+            _ => panic!("segment_weight called on non-segment node"),
         }
     }
 
+    pub(super) fn segment_is_container(&self) -> bool {
+        matches!(&self.data, NodeData::String { .. } | NodeData::Array { .. })
+    }
+
+    /// Moves the contents from `split_index` onwards into the empty segment `other`. Embeds of
+    /// strings are moved along with their placeholder characters, so `other`'s ids must already be
+    /// set.
     pub(super) fn segment_split_contents_into(&mut self, other: &mut Node<Id>, split_index: usize) {
         match (&mut self.data, &mut other.data) {
             (
                 NodeData::StringSegment {
                     contents: self_contents,
+                    embeds: self_embeds,
                     ..
                 },
                 NodeData::StringSegment {
                     contents: other_contents,
+                    embeds: other_embeds,
+                    ids: other_ids,
                     ..
                 },
             ) => {
-                if other_contents.len() != 0 {
+                if !other_contents.is_empty() {
                     panic!("split_contents_into's `other` did not have empty contents");
                 }
                 let new_string = self_contents.split_off(split_index);
                 *other_contents = new_string;
+                let moved: Vec<Id> = self_embeds
+                    .keys()
+                    .filter(|id| other_ids.iter().any(|run| run.step_of(id).is_some()))
+                    .cloned()
+                    .collect();
+                for id in moved {
+                    let child = self_embeds.remove(&id).unwrap();
+                    other_embeds.insert(id, child);
+                }
             }
             (
                 NodeData::ArraySegment {
@@ -303,7 +680,7 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
                     ..
                 },
             ) => {
-                if other_contents.len() != 0 {
+                if !other_contents.is_empty() {
                     panic!("split_contents_into's `other` did not have empty contents");
                 }
                 let new_vec = self_contents.split_off(split_index);
@@ -313,9 +690,44 @@ impl<Id: Hash + Clone + Eq + Debug> Node<Id> {
             _ => panic!("two node types in split_contents_into did not match or were not segments"),
         }
     }
+
+    /// Deletes `range` from the contents of this string segment, returning the embeds whose
+    /// placeholder characters were in it.
+    pub(super) fn segment_delete_text(&mut self, range: Range<usize>) -> Vec<Child> {
+        match &mut self.data {
+            NodeData::StringSegment {
+                contents,
+                embeds,
+                ids,
+                ..
+            } => {
+                let deleted: Vec<Id> = embeds
+                    .keys()
+                    .filter(|id| {
+                        ids.iter()
+                            .any(|run| match (run.step_of(id), run.contents_index) {
+                                (Some(step), Some(index)) => {
+                                    let index = contents[index..]
+                                        .char_indices()
+                                        .nth(step)
+                                        .map(|(offset, _)| index + offset)
+                                        .expect("embed placeholder was past end of contents");
+                                    range.contains(&index)
+                                }
+                                _ => false,
+                            })
+                    })
+                    .cloned()
+                    .collect();
+                contents.replace_range(range, "");
+                deleted.iter().filter_map(|id| embeds.remove(id)).collect()
+            }
+            _ => panic!("segment_delete_text called on non-string segment"),
+        }
+    }
 }
 
-impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> Tree<Id> {
     /// This is private since it constructs a tree with no root value; use one of the public
     /// constructors to create the `Tree` instead.
     fn new(root_id: Id) -> Self {
@@ -323,11 +735,29 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             orphans: HashSet::new(),
             next_node: NodeId(0),
             id_to_node: HashMap::new(),
+            moves: HashMap::new(),
+            moved_from: HashMap::new(),
             nodes: HashMap::new(),
             root: root_id,
         }
     }
 
+    /// Id of the root value of the tree.
+    pub fn root(&self) -> &Id {
+        &self.root
+    }
+
+    /// Runs `f` on this tree, putting the tree back the way it was if `f` returns an error. The
+    /// tree is persistent, so saving a copy to go back to is cheap.
+    pub fn atomically<T, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let original = self.clone();
+        let result = f(self);
+        if result.is_err() {
+            *self = original;
+        }
+        result
+    }
+
     pub fn update(&mut self, edit: &Edit<Id>) -> Result<(), TreeError> {
         match edit {
             Edit::ArrayCreate { id } => self.construct_array(id.0.clone()),
@@ -335,10 +765,31 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 self.insert_list_item(index.0.clone(), id.0.clone(), item.clone())
             }
             Edit::ArrayDelete { id } => self.delete_list_item(id.0.clone()).map(|_| ()),
+            Edit::ArrayMove { id, index, new_id } => {
+                self.move_list_item(id.0.clone(), index.0.clone(), new_id.0.clone())
+            }
             Edit::MapCreate { id } => self.construct_object(id.0.clone()),
             Edit::MapInsert { parent, key, item } => self
                 .object_assign(parent.0.clone(), key.clone(), item.clone())
                 .map(|_| ()),
+            Edit::MapInsertMulti {
+                parent,
+                key,
+                id,
+                item,
+                supersedes,
+            } => self.object_assign_multi(
+                parent.0.clone(),
+                key.clone(),
+                id.clone(),
+                item.clone(),
+                supersedes,
+            ),
+            Edit::CounterCreate { id } => self.construct_counter(id.0.clone()),
+            Edit::CounterAdd { id, amount } => self.counter_add(id.0.clone(), *amount),
+            Edit::SetCreate { id } => self.construct_set(id.0.clone()),
+            Edit::SetAdd { id, tag, item } => self.set_add(id.0.clone(), tag.clone(), item),
+            Edit::SetRemove { id, item, observed } => self.set_remove(id.0.clone(), item, observed),
             Edit::TextCreate { id } => self.construct_string(id.0.clone()),
             Edit::TextInsert {
                 index,
@@ -346,6 +797,27 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 character,
             } => self.insert_character(index.0.clone(), id.0.clone(), *character),
             Edit::TextDelete { id } => self.delete_character(id.0.clone()),
+            Edit::TextInsertRun { index, id, text } => {
+                self.insert_text_run(index.0.clone(), id.0.clone(), text)
+            }
+            Edit::TextDeleteRange { ids } => self.delete_character_ids(ids),
+            Edit::ArrayDeleteRange { ids } => self.delete_list_ids(ids),
+            Edit::TextInsertEmbed { index, id, item } => {
+                self.insert_embed(index.0.clone(), id.0.clone(), item.clone())
+            }
+            Edit::TextMark {
+                start,
+                end,
+                expand,
+                name,
+                value,
+            } => self.mark_text(
+                start.0.clone(),
+                end.0.clone(),
+                *expand,
+                name.clone(),
+                value.clone(),
+            ),
         }
     }
 
@@ -397,6 +869,27 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             id.clone(),
             NodeData::Object {
                 items: HashMap::new(),
+                registers: HashMap::new(),
+                id,
+            },
+        )
+        .map(|_| ())
+    }
+
+    /// Constructs a new counter with a value of zero within the `Tree`. Newly constructed values
+    /// have no parent or place in the tree until placed with an `assign` call.
+    pub(super) fn construct_counter(&mut self, id: Id) -> Result<(), TreeError> {
+        self.construct_simple(id.clone(), NodeData::Counter { value: 0, id })
+            .map(|_| ())
+    }
+
+    /// Constructs a new empty set within the `Tree`. Newly constructed values have no parent or
+    /// place in the tree until placed with an `assign` call.
+    pub(super) fn construct_set(&mut self, id: Id) -> Result<(), TreeError> {
+        self.construct_simple(
+            id.clone(),
+            NodeData::Set {
+                members: HashMap::new(),
                 id,
             },
         )
@@ -413,6 +906,8 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 id,
                 start: segment_id,
                 end: segment_id,
+                segments: SegmentIndex::new(segment_id),
+                marks: Arc::new(Vec::new()),
             },
         )?;
         self.nodes.insert(
@@ -421,6 +916,7 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 parent: Some(string_id),
                 data: NodeData::StringSegment {
                     contents: "".to_string(),
+                    embeds: HashMap::new(),
                     ids: vec![],
                     prev: string_id,
                     next: string_id,
@@ -440,6 +936,7 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 id,
                 start: segment_id,
                 end: segment_id,
+                segments: SegmentIndex::new(segment_id),
             },
         )?;
         self.nodes.insert(
@@ -481,14 +978,26 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                 None => continue,
             };
             match node.data {
-                NodeData::Object { id, items } => {
-                    for (_, val) in items {
+                NodeData::Object {
+                    id,
+                    items,
+                    registers,
+                } => {
+                    let values = registers
+                        .into_iter()
+                        .flat_map(|(_, values)| values.into_iter().map(|(_, val)| val));
+                    for val in items.into_iter().map(|(_, val)| val).chain(values) {
                         match val {
                             Child::Collection(id) => {
                                 queue.push(id);
                             }
                             // do nothing for other values; don't have any subchildren to delete
-                            Child::True | Child::False | Child::Null | Child::Int(_) => {}
+                            Child::True
+                            | Child::False
+                            | Child::Null
+                            | Child::Int(_)
+                            | Child::Float(_)
+                            | Child::Bytes(_) => {}
                         }
                     }
                     self.id_to_node.remove(&id).unwrap();
@@ -497,10 +1006,22 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                     queue.push(start);
                     self.id_to_node.remove(&id).unwrap();
                 }
-                NodeData::StringSegment { next, ids, .. } => {
+                NodeData::Counter { id, .. } | NodeData::Set { id, .. } => {
+                    self.id_to_node.remove(&id).unwrap();
+                }
+                NodeData::StringSegment {
+                    next, ids, embeds, ..
+                } => {
                     queue.push(next);
-                    for (id, _) in ids {
-                        self.id_to_node.remove(&id).unwrap();
+                    for run in ids {
+                        for step in 0..run.len {
+                            self.id_to_node.remove(&run.start.successor(step)).unwrap();
+                        }
+                    }
+                    for (_, child) in embeds {
+                        if let Child::Collection(id) = child {
+                            queue.push(id);
+                        }
                     }
                 }
                 NodeData::Array { start, id, .. } => {
@@ -514,8 +1035,24 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                     ..
                 } => {
                     queue.push(next);
-                    for (id, _) in ids {
-                        self.id_to_node.remove(&id).unwrap();
+                    for run in ids {
+                        for step in 0..run.len {
+                            let id = run.start.successor(step);
+                            match self.moves.remove(&id) {
+                                // splice the position out of the chain of moves through it
+                                Some(next) => match self.moved_from.remove(&id) {
+                                    Some(prev) => {
+                                        self.moves.insert(prev.clone(), next.clone());
+                                        self.moved_from.insert(next, prev);
+                                    }
+                                    None => {
+                                        self.moved_from.remove(&next);
+                                    }
+                                },
+                                None => self.forget_moves(&id),
+                            }
+                            self.id_to_node.remove(&id).unwrap();
+                        }
                     }
                     for item in contents {
                         match item {
@@ -523,7 +1060,12 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
                                 queue.push(id);
                             }
                             // do nothing for other values; don't have any subchildren to delete
-                            Child::True | Child::False | Child::Null | Child::Int(_) => {}
+                            Child::True
+                            | Child::False
+                            | Child::Null
+                            | Child::Int(_)
+                            | Child::Float(_)
+                            | Child::Bytes(_) => {}
                         }
                     }
                 }
@@ -538,9 +1080,23 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
 
     // has to recurse up parents to ensure we haven't made any cycles, unfortunately
     fn reparent_item(&mut self, item: NodeId, parent: NodeId) -> Result<(), TreeError> {
+        self.check_reparent(item, parent)?;
+        self.orphans.remove(&item).unwrap();
+        self.nodes[&item].parent = Some(parent);
+        Ok(())
+    }
+
+    /// Returns the error `reparent_item` would return for these arguments, without changing
+    /// anything.
+    fn check_reparent(&self, item: NodeId, parent: NodeId) -> Result<(), TreeError> {
         if self.nodes[&item].parent.is_some() {
             return Err(TreeError::NodeAlreadyHadParent);
         }
+        self.check_cycle(item, parent)
+    }
+
+    /// Returns an error if `parent` is `item` or one of its descendants.
+    fn check_cycle(&self, item: NodeId, parent: NodeId) -> Result<(), TreeError> {
         let mut next = Some(parent);
         while let Some(this) = next.take() {
             if this == item {
@@ -548,9 +1104,6 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             }
             next = self.nodes[&this].parent;
         }
-
-        self.orphans.remove(&item).unwrap();
-        self.nodes[&item].parent = Some(parent);
         Ok(())
     }
 
@@ -558,15 +1111,19 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
         match value {
             Value::Object(value::ObjectRef(id))
             | Value::Array(value::ArrayRef(id))
-            | Value::String(value::StringRef(id)) => {
+            | Value::String(value::StringRef(id))
+            | Value::Counter(value::CounterRef(id))
+            | Value::Set(value::SetRef(id)) => {
                 // TODO should we validate types here?
-                let node_id = self.id_to_node(&id)?;
+                let node_id = self.id_to_node(id)?;
                 Ok(Some(Child::Collection(node_id)))
             }
             Value::True => Ok(Some(Child::True)),
             Value::False => Ok(Some(Child::False)),
             Value::Null => Ok(Some(Child::Null)),
             Value::Int(i) => Ok(Some(Child::Int(*i))),
+            Value::Float(f) => Ok(Some(Child::Float(value::float_bits(*f)))),
+            Value::Bytes(bytes) => Ok(Some(Child::Bytes(bytes.as_slice().into()))),
             Value::Unset => Ok(None),
         }
     }
@@ -578,14 +1135,18 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             Some(Child::False) => Value::False,
             Some(Child::Null) => Value::Null,
             Some(Child::Int(i)) => Value::Int(*i),
+            Some(Child::Float(bits)) => Value::Float(f64::from_bits(*bits)),
+            Some(Child::Bytes(bytes)) => Value::Bytes(bytes.to_vec()),
             Some(Child::Collection(node_id)) => {
-                let id = self.nodes[&node_id]
+                let id = self.nodes[node_id]
                     .id()
                     .expect("segment was somehow child of object?");
                 match self.get_type(id.clone()) {
                     Ok(NodeType::String) => Value::String(value::StringRef(id)),
                     Ok(NodeType::Object) => Value::Object(value::ObjectRef(id)),
                     Ok(NodeType::Array) => Value::Array(value::ArrayRef(id)),
+                    Ok(NodeType::Counter) => Value::Counter(value::CounterRef(id)),
+                    Ok(NodeType::Set) => Value::Set(value::SetRef(id)),
                     _ => panic!("collection id did not have type of collection"),
                 }
             }
@@ -593,16 +1154,12 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
     }
 
     pub(super) fn id_to_node(&self, id: &Id) -> Result<NodeId, TreeError> {
-        self.id_to_node
-            .get(id)
-            .ok_or(TreeError::UnknownId)
-            .map(|v| *v)
+        self.id_to_node.get(id).ok_or(TreeError::UnknownId).copied()
     }
 
-    // TODO right now this is last-write-wins, could modify the object NodeData pretty lightly and
-    // get multi value registers which would be sick
     /// Moves `value` to `object[key]`. If `value` is `None`, the key is deleted. If there was a
     /// previous collection assigned to this key, it is reparented into the tree's `orphan` list.
+    /// This is last-write-wins; see `object_assign_multi` for keeping concurrent writes.
     pub(super) fn object_assign(
         &mut self,
         object: Id,
@@ -615,14 +1172,22 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             self.reparent_item(*child, object_node_id)?;
         }
         match &mut self.nodes[&object_node_id].data {
-            NodeData::Object { items, id: _ } => {
+            NodeData::Object {
+                items, registers, ..
+            } => {
+                // every value but the last is only stored in the register
+                let mut replaced = registers.remove(&key).unwrap_or_default();
+                replaced.pop();
                 let old = if let Some(child) = child_opt {
                     items.insert(key, child)
                 } else {
                     items.remove(&key)
                 };
-                if let Some(Child::Collection(old_id)) = old {
-                    self.move_to_orphan(old_id);
+                let replaced = replaced.into_iter().map(|(_, child)| child);
+                for child in old.iter().cloned().chain(replaced) {
+                    if let Child::Collection(old_id) = child {
+                        self.move_to_orphan(old_id);
+                    }
                 }
                 Ok(self.child_to_value(old.as_ref()))
             }
@@ -630,6 +1195,170 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
         }
     }
 
+    /// Adds `amount` to the counter `counter`, wrapping around on overflow.
+    pub(super) fn counter_add(&mut self, counter: Id, amount: i64) -> Result<(), TreeError> {
+        let node_id = self.id_to_node(&counter)?;
+        match &mut self.nodes[&node_id].data {
+            NodeData::Counter { value, .. } => {
+                *value = value.wrapping_add(amount);
+                Ok(())
+            }
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns `value` as a set member, or `UnsupportedValue` if it's a collection or unset.
+    pub(super) fn set_member(&self, value: &Value<Id>) -> Result<Child, TreeError> {
+        match self.value_to_child(value)? {
+            Some(Child::Collection(_)) | None => Err(TreeError::UnsupportedValue),
+            Some(child) => Ok(child),
+        }
+    }
+
+    /// Adds `value` to the set `set`, tagged with `tag`.
+    pub(super) fn set_add(&mut self, set: Id, tag: Id, value: &Value<Id>) -> Result<(), TreeError> {
+        let member = self.set_member(value)?;
+        let node_id = self.id_to_node(&set)?;
+        match &mut self.nodes[&node_id].data {
+            NodeData::Set { members, .. } => {
+                let tags = members.entry(member).or_default();
+                if tags.contains(&tag) {
+                    return Err(TreeError::DuplicateId);
+                }
+                tags.push(tag);
+                Ok(())
+            }
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Removes the tags in `observed` from `value` in the set `set`. `value` stays in the set if
+    /// it has any other tags, which means it was added concurrently with this removal.
+    pub(super) fn set_remove(
+        &mut self,
+        set: Id,
+        value: &Value<Id>,
+        observed: &[Id],
+    ) -> Result<(), TreeError> {
+        let member = self.set_member(value)?;
+        let node_id = self.id_to_node(&set)?;
+        match &mut self.nodes[&node_id].data {
+            NodeData::Set { members, .. } => {
+                if let Some(tags) = members.get_mut(&member) {
+                    tags.retain(|tag| !observed.contains(tag));
+                    if tags.is_empty() {
+                        members.remove(&member);
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns the tags of the `Edit::SetAdd`s that put `value` in the set `set`. This is empty if
+    /// `value` isn't in the set.
+    pub(crate) fn set_tags(&self, set: &Id, value: &Value<Id>) -> Result<Vec<Id>, TreeError> {
+        let member = self.set_member(value)?;
+        let node_id = self.id_to_node(set)?;
+        match &self.nodes[&node_id].data {
+            NodeData::Set { members, .. } => Ok(members.get(&member).cloned().unwrap_or_default()),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Adds `value` to the values of `object[key]`, as the write `write_id`, and removes the
+    /// values written by the writes in `supersedes`. If `value` is `None`, nothing is added. A
+    /// value that was set by `object_assign` has no write id, and is always replaced. Collections
+    /// that are removed are reparented into the tree's `orphan` list.
+    pub(super) fn object_assign_multi(
+        &mut self,
+        object: Id,
+        key: String,
+        write_id: Id,
+        value: Value<Id>,
+        supersedes: &[Id],
+    ) -> Result<(), TreeError> {
+        let child_opt = self.value_to_child(&value)?;
+        let object_node_id = self.id_to_node(&object)?;
+        match &self.nodes[&object_node_id].data {
+            NodeData::Object { registers, .. } => {
+                let values = registers.get(&key).map(Vec::as_slice).unwrap_or_default();
+                if values.iter().any(|(id, _)| id == &write_id) {
+                    return Err(TreeError::DuplicateId);
+                }
+            }
+            _ => return Err(TreeError::UnexpectedNodeType),
+        }
+        if let Some(Child::Collection(child)) = &child_opt {
+            self.reparent_item(*child, object_node_id)?;
+        }
+        let removed = match &mut self.nodes[&object_node_id].data {
+            NodeData::Object {
+                items, registers, ..
+            } => {
+                let mut removed = Vec::new();
+                let mut values = match registers.remove(&key) {
+                    Some(values) => values,
+                    None => {
+                        removed.extend(items.remove(&key));
+                        Vec::new()
+                    }
+                };
+                values.retain(|(id, child)| {
+                    let keep = !supersedes.contains(id);
+                    if !keep {
+                        removed.push(child.clone());
+                    }
+                    keep
+                });
+                values.extend(child_opt.map(|child| (write_id, child)));
+                match values.last() {
+                    Some((_, last)) => {
+                        items.insert(key.clone(), last.clone());
+                        registers.insert(key, values);
+                    }
+                    None => {
+                        items.remove(&key);
+                    }
+                }
+                removed
+            }
+            _ => unreachable!("object type was checked"),
+        };
+        for child in removed {
+            if let Child::Collection(id) = child {
+                self.move_to_orphan(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every value of `object[key]`, in the order they were written.
+    pub(super) fn object_values(
+        &self,
+        object: &Id,
+        key: &str,
+    ) -> Result<Vec<Value<Id>>, TreeError> {
+        let object_node_id = self.id_to_node(object)?;
+        match &self.nodes[&object_node_id].data {
+            NodeData::Object {
+                items, registers, ..
+            } => Ok(match registers.get(key) {
+                Some(values) => values
+                    .iter()
+                    .map(|(_, child)| self.child_to_value(Some(child)))
+                    .collect(),
+                None => items
+                    .get(key)
+                    .map(|child| self.child_to_value(Some(child)))
+                    .into_iter()
+                    .collect(),
+            }),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
     /// Gets the type of `Id`.
     pub(super) fn get_type(&self, id: Id) -> Result<NodeType, TreeError> {
         let node_id = self.id_to_node(&id)?;
@@ -639,6 +1368,8 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             .expect("node_id listed in id_to_node did not exist.");
         match node.data {
             NodeData::Object { .. } => Ok(NodeType::Object),
+            NodeData::Counter { .. } => Ok(NodeType::Counter),
+            NodeData::Set { .. } => Ok(NodeType::Set),
             NodeData::String { .. } => Ok(NodeType::String),
             NodeData::StringSegment { .. } => Ok(NodeType::Character),
             NodeData::Array { .. } => Ok(NodeType::Array),
@@ -677,7 +1408,7 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
         character_id: Id,
         character: char,
     ) -> Result<(), TreeError> {
-        sequence::insert(self, append_id, character_id, |string_index, node| {
+        sequence::insert(self, append_id, character_id, 1, |string_index, node| {
             match &mut node.data {
                 NodeData::StringSegment { contents, .. } => {
                     contents.insert(string_index, character);
@@ -688,16 +1419,112 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
         })
     }
 
+    /// Like `insert_character`, but inserts every character of `text`, with ids `first_id`,
+    /// `first_id.successor(1)`, and so on. The characters are stored as a single run.
+    pub(super) fn insert_text_run(
+        &mut self,
+        append_id: Id,
+        first_id: Id,
+        text: &str,
+    ) -> Result<(), TreeError> {
+        let len = text.chars().count();
+        sequence::insert(self, append_id, first_id, len, |string_index, node| {
+            match &mut node.data {
+                NodeData::StringSegment { contents, .. } => {
+                    contents.insert_str(string_index, text);
+                }
+                _ => panic!("unknown object type!!"),
+            }
+            text.len()
+        })
+    }
+
     /// Deletes the character with ID `char_id`. A tombstone is left in the string, allowing future
     /// `insert_character` calls to reference this `char_id` as their `append_id`.
+    /// If the character was an embed placeholder, the embed is removed too.
     pub(super) fn delete_character(&mut self, char_id: Id) -> Result<(), TreeError> {
-        sequence::delete(self, char_id, |string_index, node| match &mut node.data {
-            NodeData::StringSegment { contents, .. } => {
-                let deleted_char = contents.remove(string_index);
-                deleted_char.len_utf8()
+        let mut removed = Vec::new();
+        sequence::delete(self, char_id, |range, node| {
+            removed.extend(node.segment_delete_text(range));
+        })?;
+        self.orphan_embeds(removed);
+        Ok(())
+    }
+
+    /// Deletes every character in `ids`, leaving tombstones. The characters must all be in the
+    /// same string.
+    pub(super) fn delete_character_ids(&mut self, ids: &[IdRange<Id>]) -> Result<(), TreeError> {
+        let first = match ids.first() {
+            Some(run) => run.start.clone(),
+            None => return Ok(()),
+        };
+        if self.get_type(first.clone())? != NodeType::Character {
+            return Err(TreeError::UnexpectedNodeType);
+        }
+        let string = self.string_container(&first)?;
+        let mut removed = Vec::new();
+        sequence::delete_ids(self, string, ids, |range, node| {
+            removed.extend(node.segment_delete_text(range));
+        })?;
+        self.orphan_embeds(removed);
+        Ok(())
+    }
+
+    /// Inserts `value` into a string after `append_id`, as the placeholder character `embed_id`.
+    /// `append_id` is either a character or the string itself, like in `insert_character`.
+    pub(super) fn insert_embed(
+        &mut self,
+        append_id: Id,
+        embed_id: Id,
+        value: Value<Id>,
+    ) -> Result<(), TreeError> {
+        let child = self
+            .value_to_child(&value)?
+            .ok_or(TreeError::UnsupportedValue)?;
+        // check everything that can fail before changing anything
+        let string = self.string_container(&append_id)?;
+        if let Child::Collection(child) = &child {
+            self.check_reparent(*child, string)?;
+        }
+        self.insert_character(append_id, embed_id.clone(), value::EMBED_CHAR)?;
+        if let Child::Collection(child) = &child {
+            self.reparent_item(*child, string)?;
+        }
+        let segment = self.id_to_node(&embed_id)?;
+        match &mut self.nodes[&segment].data {
+            NodeData::StringSegment { embeds, .. } => {
+                embeds.insert(embed_id, child);
             }
-            _ => panic!("unknown object type!!"),
-        })
+            _ => unreachable!("placeholder character was not in a string segment"),
+        }
+        Ok(())
+    }
+
+    /// Moves the collections among `removed`, the embeds of deleted placeholder characters, into
+    /// the tree's `orphan` list.
+    fn orphan_embeds(&mut self, removed: Vec<Child>) {
+        for child in removed {
+            if let Child::Collection(id) = child {
+                self.move_to_orphan(id);
+            }
+        }
+    }
+
+    /// Returns the value embedded by the character `char_id`, or `None` if it isn't an embed
+    /// placeholder.
+    pub(super) fn embedded_value(&self, char_id: &Id) -> Result<Option<Value<Id>>, TreeError> {
+        Ok(self
+            .embedded_child(char_id)?
+            .map(|child| self.child_to_value(Some(child))))
+    }
+
+    /// Like `embedded_value`, but returns the embed as it's stored.
+    pub(super) fn embedded_child(&self, char_id: &Id) -> Result<Option<&Child>, TreeError> {
+        match &self.nodes[&self.id_to_node(char_id)?].data {
+            NodeData::StringSegment { embeds, .. } => Ok(embeds.get(char_id)),
+            NodeData::String { .. } => Ok(None),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
     }
 
     /// Creates `character` in the tree with id `character_id`, and immediately inserts it after
@@ -716,24 +1543,10 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
             None => return Ok(()),
         };
         if let Child::Collection(child) = &child {
-            let append_node = self.id_to_node(&append_id)?;
-            match self.nodes[&append_node] {
-                Node {
-                    data: NodeData::ArraySegment { .. },
-                    parent,
-                } => {
-                    self.reparent_item(*child, parent.unwrap())?;
-                }
-                Node {
-                    data: NodeData::Array { .. },
-                    ..
-                } => {
-                    self.reparent_item(*child, append_node)?;
-                }
-                _ => return Err(TreeError::UnexpectedNodeType),
-            }
+            let array = self.list_container(&append_id)?;
+            self.reparent_item(*child, array)?;
         }
-        sequence::insert(self, append_id, character_id, |array_index, node| {
+        sequence::insert(self, append_id, character_id, 1, |array_index, node| {
             match &mut node.data {
                 NodeData::ArraySegment { contents, .. } => {
                     contents.insert(array_index, child);
@@ -744,20 +1557,208 @@ impl<Id: Hash + Clone + Eq + Debug> Tree<Id> {
         })
     }
 
+    /// Returns the id that an item must be inserted after to end up at position `offset` in the
+    /// string or array `sequence`. Takes `O(log n)`.
+    pub(crate) fn sequence_index_at(&self, sequence: &Id, offset: usize) -> Result<Id, TreeError> {
+        sequence::index_at(self, sequence, offset, TextUnit::Scalar)
+    }
+
+    /// Returns the ids of the `len` items starting at position `start` in the string or array
+    /// `sequence`. Takes `O(log n + len)`.
+    pub(crate) fn sequence_ids(
+        &self,
+        sequence: &Id,
+        start: usize,
+        len: usize,
+    ) -> Result<Vec<Id>, TreeError> {
+        sequence::live_ids(self, sequence, start, len)
+    }
+
+    /// Returns the ids of the `Edit::MapInsertMulti` writes that set the current values of
+    /// `object[key]`.
+    pub(crate) fn object_write_ids(&self, object: &Id, key: &str) -> Result<Vec<Id>, TreeError> {
+        let object_node_id = self.id_to_node(object)?;
+        match &self.nodes[&object_node_id].data {
+            NodeData::Object { registers, .. } => Ok(registers
+                .get(key)
+                .map(|values| values.iter().map(|(id, _)| id.clone()).collect())
+                .unwrap_or_default()),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns the number of items in the string or array `sequence`.
+    pub(crate) fn sequence_len(&self, sequence: &Id) -> Result<usize, TreeError> {
+        sequence::len(self, sequence)
+    }
+
     /// Deletes the item in the list with ID `item_id`. A tombstone is left in the string, allowing
     /// future `insert_character` calls to reference this `char_id` as their `append_id`.
+    /// If the item was moved with `move_list_item`, the item is deleted from wherever it was
+    /// moved to.
     pub(super) fn delete_list_item(&mut self, item_id: Id) -> Result<Value<Id>, TreeError> {
+        let item_id = self.moved_list_item(item_id);
         let mut child_opt = None;
-        sequence::delete(self, item_id, |array_index, node| match &mut node.data {
+        sequence::delete(self, item_id.clone(), |range, node| match &mut node.data {
             NodeData::ArraySegment { contents, .. } => {
-                child_opt = Some(contents.remove(array_index));
-                1
+                child_opt = contents.drain(range).next();
             }
             _ => panic!("unknown object type!!"),
         })?;
         if let Some(Child::Collection(id)) = &child_opt {
             self.move_to_orphan(*id);
         }
+        self.forget_moves(&item_id);
         Ok(self.child_to_value(child_opt.as_ref()))
     }
+
+    /// Deletes every entry in `ids`, leaving tombstones. The entries must all be in the same
+    /// array. Collections in the removed entries are moved to the orphan list. Like
+    /// `delete_list_item`, the delete follows entries that have since been moved.
+    pub(super) fn delete_list_ids(&mut self, ids: &[IdRange<Id>]) -> Result<(), TreeError> {
+        let first = match ids.first() {
+            Some(run) => run.start.clone(),
+            None => return Ok(()),
+        };
+        if self.get_type(first.clone())? != NodeType::ArrayEntry {
+            return Err(TreeError::UnexpectedNodeType);
+        }
+        let array = self.list_container(&first)?;
+        let mut removed = Vec::new();
+        sequence::delete_ids(self, array, ids, |range, node| match &mut node.data {
+            NodeData::ArraySegment { contents, .. } => {
+                removed.extend(contents.drain(range));
+            }
+            _ => panic!("unknown object type!!"),
+        })?;
+        for child in removed {
+            if let Child::Collection(id) = child {
+                self.move_to_orphan(id);
+            }
+        }
+        if !self.moves.is_empty() {
+            let ids: Vec<Id> = ids
+                .iter()
+                .flat_map(|run| (0..run.len).map(move |n| run.start.successor(n)))
+                .collect();
+            for id in ids {
+                if self.moves.contains_key(&id) {
+                    self.delete_list_item(id)?;
+                } else {
+                    self.forget_moves(&id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the node of the array containing `item_id`, which may be an entry or the array
+    /// itself.
+    fn list_container(&self, item_id: &Id) -> Result<NodeId, TreeError> {
+        let node_id = self.id_to_node(item_id)?;
+        match &self.nodes[&node_id] {
+            Node {
+                data: NodeData::ArraySegment { .. },
+                parent,
+            } => Ok(parent.expect("segment did not have a container")),
+            Node {
+                data: NodeData::Array { .. },
+                ..
+            } => Ok(node_id),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns the node of the string containing `char_id`, which may be a character or the
+    /// string itself.
+    fn string_container(&self, char_id: &Id) -> Result<NodeId, TreeError> {
+        let node_id = self.id_to_node(char_id)?;
+        match &self.nodes[&node_id] {
+            Node {
+                data: NodeData::StringSegment { .. },
+                parent,
+            } => Ok(parent.expect("segment did not have a container")),
+            Node {
+                data: NodeData::String { .. },
+                ..
+            } => Ok(node_id),
+            _ => Err(TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Follows `item_id` through any moves to the id of the entry's current position.
+    fn moved_list_item(&self, mut item_id: Id) -> Id {
+        while let Some(next) = self.moves.get(&item_id) {
+            item_id = next.clone();
+        }
+        item_id
+    }
+
+    /// Drops the forwarding to `item_id` from the positions the entry was moved away from, once
+    /// the entry is deleted. Later edits of those positions then find a tombstone, just as they
+    /// would have by following the moves to the deleted entry.
+    fn forget_moves(&mut self, item_id: &Id) {
+        let mut id = item_id.clone();
+        while let Some(prev) = self.moved_from.remove(&id) {
+            self.moves.remove(&prev);
+            id = prev;
+        }
+    }
+
+    /// Moves the list item `item_id` to directly after `append_id`, giving it the id `new_id`.
+    /// A tombstone is left at the item's old position, which forwards later moves and deletes of
+    /// `item_id` to `new_id` until the entry is deleted. Moving an item that has been deleted
+    /// creates `new_id` as a tombstone, so later edits can still use it as a position.
+    pub(super) fn move_list_item(
+        &mut self,
+        item_id: Id,
+        append_id: Id,
+        new_id: Id,
+    ) -> Result<(), TreeError> {
+        let item_id = self.moved_list_item(item_id);
+        if self.get_type(item_id.clone())? != NodeType::ArrayEntry {
+            return Err(TreeError::UnexpectedNodeType);
+        }
+        if self.id_to_node.contains_key(&new_id) {
+            return Err(TreeError::DuplicateId);
+        }
+        let array = self.list_container(&append_id)?;
+        let child = match sequence::contents_index(self, &item_id)? {
+            (segment, Some(contents_index)) => match &self.nodes[&segment].data {
+                NodeData::ArraySegment { contents, .. } => Some(contents[contents_index].clone()),
+                _ => panic!("unknown object type!!"),
+            },
+            (_, None) => None,
+        };
+        if let Some(Child::Collection(collection)) = &child {
+            self.check_cycle(*collection, array)?;
+        }
+
+        sequence::delete(self, item_id.clone(), |range, node| match &mut node.data {
+            NodeData::ArraySegment { contents, .. } => {
+                contents.drain(range);
+            }
+            _ => panic!("unknown object type!!"),
+        })?;
+        if let Some(Child::Collection(collection)) = &child {
+            self.nodes[collection].parent = Some(array);
+        }
+        let live = child.is_some();
+        sequence::insert(self, append_id, new_id.clone(), 1, |array_index, node| {
+            match &mut node.data {
+                NodeData::ArraySegment { contents, .. } => {
+                    contents.insert(array_index, child.unwrap_or(Child::Null));
+                }
+                _ => panic!("unknown object type!!"),
+            }
+            1
+        })?;
+        if live {
+            self.moves.insert(item_id.clone(), new_id.clone());
+            self.moved_from.insert(new_id, item_id);
+        } else {
+            self.delete_list_item(new_id)?;
+        }
+        Ok(())
+    }
 }
@@ -1,28 +1,95 @@
+use im::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::hash::Hash;
 
-use super::tree;
+use super::sequence;
+use super::text::TextUnit;
+use super::tree::{self, SequentialId};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// A value in the tree. Floats compare by their bit pattern rather than numerically, so that
+/// equality is deterministic: every NaN equals every other NaN, and `0.0` doesn't equal `-0.0`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Value<Id> {
     String(StringRef<Id>),
     Array(ArrayRef<Id>),
     Object(ObjectRef<Id>),
+    Counter(CounterRef<Id>),
+    Set(SetRef<Id>),
     Int(i64),
+    /// Serialized as the bits of the float, since formats like JSON can't represent NaN, the
+    /// infinities or every NaN payload.
+    Float(#[serde(with = "float_as_bits")] f64),
+    /// An immutable blob of bytes. Unlike a string, it can't be edited in place, only replaced
+    /// as a whole.
+    Bytes(Vec<u8>),
     True,
     False,
     Null,
     Unset,
 }
 
+impl<Id: PartialEq> PartialEq for Value<Id> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => a == b,
+            (Value::Counter(a), Value::Counter(b)) => a == b,
+            (Value::Set(a), Value::Set(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => float_bits(*a) == float_bits(*b),
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::True, Value::True)
+            | (Value::False, Value::False)
+            | (Value::Null, Value::Null)
+            | (Value::Unset, Value::Unset) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<Id: Eq> Eq for Value<Id> {}
+
+/// Returns the bits of `float`, with every NaN replaced by the same canonical NaN. Floats are
+/// stored in the tree like this, so replicas agree on them regardless of NaN payloads.
+pub(super) fn float_bits(float: f64) -> u64 {
+    if float.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        float.to_bits()
+    }
+}
+
+/// Serde representation of `Value::Float`, as the `u64` returned by `f64::to_bits`.
+mod float_as_bits {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(float: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        float.to_bits().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        u64::deserialize(deserializer).map(f64::from_bits)
+    }
+}
+
+/// The character that holds the place of a value embedded in a string with
+/// `Edit::TextInsertEmbed`. It shows up in `StringRef::to_string` and `StringRef::iter`, and
+/// `StringRef::chunks` replaces it with the embedded value.
+pub const EMBED_CHAR: char = '\u{fffc}';
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Parent<Id> {
     Array(ArrayRef<Id>),
     Object(ObjectRef<Id>),
+    /// The value is embedded in a string.
+    String(StringRef<Id>),
     None,
 }
 
-pub(super) fn get_parent<Id: Hash + Clone + Eq + Debug>(
+pub(super) fn get_parent<Id: Hash + Clone + Eq + Debug + SequentialId>(
     tree: &tree::Tree<Id>,
     id: &Id,
 ) -> Result<Parent<Id>, tree::TreeError> {
@@ -33,13 +100,14 @@ pub(super) fn get_parent<Id: Hash + Clone + Eq + Debug>(
     match tree.get_type(id.clone()) {
         Ok(tree::NodeType::Array) => Ok(Parent::Array(ArrayRef(id))),
         Ok(tree::NodeType::Object) => Ok(Parent::Object(ObjectRef(id))),
+        Ok(tree::NodeType::String) => Ok(Parent::String(StringRef(id))),
         e => panic!("parent was of unexpected type: {:?}", e),
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StringRef<Id>(pub Id);
-impl<Id: Hash + Clone + Eq + Debug> StringRef<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> StringRef<Id> {
     pub fn to_string(&self, tree: &tree::Tree<Id>) -> Result<String, tree::TreeError> {
         let string_node_id = tree.id_to_node(&self.0)?;
         let node = tree
@@ -84,22 +152,164 @@ impl<Id: Hash + Clone + Eq + Debug> StringRef<Id> {
             _ => return Err(tree::TreeError::UnexpectedNodeType),
         };
         let last_node = tree.nodes.get(&last_node_id).unwrap();
-        match &last_node.data {
-            tree::NodeData::StringSegment { ids, .. } => {
-                Ok(StringIndex(ids.last().unwrap().0.clone()))
-            }
+        let ids = last_node.segment_ids()?;
+        Ok(StringIndex(match ids.last() {
+            Some(run) => run.start.successor(run.len - 1),
+            // the string has never had any characters
+            None => self.0.clone(),
+        }))
+    }
+
+    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
+        get_parent(tree, &self.0)
+    }
+
+    /// Returns an iterator over the `(StringIndex, char)` pairs of the string's live characters,
+    /// in order. Characters are read lazily as the iterator advances.
+    pub fn iter<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<StringIter<'a, Id>, tree::TreeError> {
+        let container = tree.id_to_node(&self.0)?;
+        let segment = match &tree.nodes[&container].data {
+            tree::NodeData::String { start, .. } => *start,
+            _ => return Err(tree::TreeError::UnexpectedNodeType),
+        };
+        Ok(StringIter::new(tree, container, segment))
+    }
+
+    /// Returns an iterator over the string as runs of text and embedded values, in order.
+    /// Characters are read lazily as the iterator advances.
+    pub fn chunks<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<TextChunks<'a, Id>, tree::TreeError> {
+        Ok(TextChunks {
+            tree,
+            characters: self.iter(tree)?.peekable(),
+        })
+    }
+
+    pub(super) fn check_type(&self, tree: &tree::Tree<Id>) -> Result<(), tree::TreeError> {
+        match tree.get_type(self.0.clone())? {
+            tree::NodeType::String => Ok(()),
             _ => Err(tree::TreeError::UnexpectedNodeType),
         }
     }
 
-    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
-        get_parent(&tree, &self.0)
+    /// Returns the number of characters in the string.
+    pub fn len(&self, tree: &tree::Tree<Id>) -> Result<usize, tree::TreeError> {
+        self.check_type(tree)?;
+        sequence::len(tree, &self.0)
+    }
+
+    pub fn is_empty(&self, tree: &tree::Tree<Id>) -> Result<bool, tree::TreeError> {
+        Ok(self.len(tree)? == 0)
+    }
+
+    /// Returns the index of a cursor at character offset `offset`, which is the index a
+    /// `TextInsert` at that offset should be anchored to. Offset `0` is the start of the string,
+    /// and otherwise this is the character just before the cursor. Takes `O(log n)`.
+    pub fn index_at(
+        &self,
+        tree: &tree::Tree<Id>,
+        offset: usize,
+    ) -> Result<StringIndex<Id>, tree::TreeError> {
+        self.index_at_unit(tree, offset, TextUnit::Scalar)
+    }
+}
+
+/// Iterator over the characters of a string, returned by `StringRef::iter`.
+pub struct StringIter<'a, Id: Hash + Clone + Eq + Debug + SequentialId> {
+    tree: &'a tree::Tree<Id>,
+    /// The `String` node, which the last segment links back to.
+    container: tree::NodeId,
+    /// The segment currently being read.
+    segment: tree::NodeId,
+    /// Remaining items in the current segment.
+    items: sequence::SegmentItems<'a, Id>,
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> StringIter<'a, Id> {
+    fn new(tree: &'a tree::Tree<Id>, container: tree::NodeId, segment: tree::NodeId) -> Self {
+        StringIter {
+            tree,
+            container,
+            segment,
+            items: sequence::items(&tree.nodes[&segment]),
+        }
+    }
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> Iterator for StringIter<'a, Id> {
+    type Item = (StringIndex<Id>, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.items.next() {
+                Some((id, Some(index))) => {
+                    let character = match &self.tree.nodes[&self.segment].data {
+                        tree::NodeData::StringSegment { contents, .. } => contents[index..]
+                            .chars()
+                            .next()
+                            .expect("segment id pointed past end of contents"),
+                        _ => panic!("node_id listed in segment adjacency was not a string segment"),
+                    };
+                    return Some((StringIndex(id), character));
+                }
+                Some((_, None)) => {}
+                None => {
+                    let tree = self.tree;
+                    let next = *tree.nodes[&self.segment].segment_adjacencies().1;
+                    if next == self.container {
+                        return None;
+                    }
+                    self.segment = next;
+                    self.items = sequence::items(&tree.nodes[&next]);
+                }
+            }
+        }
     }
 }
 
+/// A piece of a string, returned by `StringRef::chunks`.
 #[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextChunk<Id> {
+    /// A run of consecutive characters that aren't embeds.
+    Text(String),
+    /// A value embedded in the string, along with the index of the character holding its place.
+    Embed(StringIndex<Id>, Value<Id>),
+}
+
+/// Iterator over the text runs and embeds of a string, returned by `StringRef::chunks`.
+pub struct TextChunks<'a, Id: Hash + Clone + Eq + Debug + SequentialId> {
+    tree: &'a tree::Tree<Id>,
+    characters: std::iter::Peekable<StringIter<'a, Id>>,
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> Iterator for TextChunks<'a, Id> {
+    type Item = TextChunk<Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, character) = self.characters.next()?;
+        if let Ok(Some(value)) = self.tree.embedded_value(&index.0) {
+            return Some(TextChunk::Embed(index, value));
+        }
+        let mut text = character.to_string();
+        while let Some((index, character)) = self.characters.peek() {
+            if let Ok(Some(_)) = self.tree.embedded_child(&index.0) {
+                break;
+            }
+            text.push(*character);
+            self.characters.next();
+        }
+        Some(TextChunk::Text(text))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StringIndex<Id>(pub Id);
-impl<Id: Hash + Clone + Eq + Debug> StringIndex<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> StringIndex<Id> {
     pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<StringRef<Id>, tree::TreeError> {
         match tree.get_type(self.0.clone()) {
             Ok(tree::NodeType::String) => Ok(StringRef(self.0.clone())),
@@ -112,103 +322,43 @@ impl<Id: Hash + Clone + Eq + Debug> StringIndex<Id> {
         }
     }
 
+    /// Returns the character offset of a cursor placed at this index; the inverse of
+    /// `StringRef::index_at`. A deleted character resolves to the offset it would have had if it
+    /// were never inserted. Takes `O(log n)`.
+    pub fn offset(&self, tree: &tree::Tree<Id>) -> Result<usize, tree::TreeError> {
+        self.offset_in(tree, TextUnit::Scalar)
+    }
+
     fn adjacent_next(
         &self,
         tree: &tree::Tree<Id>,
         backwards: bool,
     ) -> Result<StringIndex<Id>, tree::TreeError> {
-        let node_id = tree.id_to_node(&self.0)?;
-        let mut this_node = tree
-            .nodes
-            .get(&node_id)
-            .expect("node_id listed in id_to_node did not exist.");
-        let mut this_index = match &this_node.data {
-            tree::NodeData::StringSegment { ids, .. } => {
-                let pos = ids.iter().position(|(id, _)| id == &self.0).unwrap();
-                pos
-            }
-            tree::NodeData::String { start, .. } => 0,
-            _ => return Err(tree::TreeError::UnexpectedNodeType),
-        };
-
-        loop {
-            let (next_node, next_index) = match &this_node.data {
-                tree::NodeData::String { start, .. } => {
-                    if backwards {
-                        // started at start of string and going backwards; return self
-                        return Ok(self.clone());
-                    }
-                    (tree.nodes.get(&start).unwrap(), None)
-                }
-                tree::NodeData::StringSegment {
-                    ids, next, prev, ..
-                } => {
-                    if (backwards && this_index > 0) || (!backwards && this_index + 1 < ids.len()) {
-                        (
-                            this_node,
-                            Some(if backwards {
-                                this_index - 1
-                            } else {
-                                this_index + 1
-                            }),
-                        )
-                    } else {
-                        if backwards {
-                            (tree.nodes.get(&prev).unwrap(), None)
-                        } else {
-                            (tree.nodes.get(&next).unwrap(), None)
-                        }
-                    }
-                }
-                _ => return Err(tree::TreeError::UnexpectedNodeType),
-            };
-
-            match (&this_node.data, &next_node.data) {
-                (tree::NodeData::String { .. }, tree::NodeData::String { .. }) => {
-                    panic!("invalid string data structure")
-                }
-                (tree::NodeData::StringSegment { ids, .. }, tree::NodeData::String { id, .. }) => {
-                    // hit edge of string; return
-                    if backwards {
-                        return Ok(StringIndex(id.clone()));
-                    } else {
-                        return Ok(StringIndex(ids[this_index].0.clone()));
-                    }
-                }
-                (_, tree::NodeData::StringSegment { ids, .. }) => {
-                    this_node = next_node;
-                    this_index = next_index.unwrap_or(if backwards && ids.len() > 0 {
-                        ids.len() - 1
-                    } else {
-                        0
-                    });
-                    if ids.len() > 0 {
-                        if ids[this_index].1.is_some() {
-                            return Ok(StringIndex(ids[this_index].0.clone()));
-                        }
-                    }
-                }
-                _ => panic!("invalid node types"),
-            }
+        let string = self.parent(tree)?;
+        match sequence::adjacent_live(tree, &self.0, backwards)? {
+            Some(id) => Ok(StringIndex(id)),
+            // hit edge of string
+            None if backwards => Ok(StringIndex(string.0)),
+            None => string.end(tree),
         }
     }
 
     pub fn still_exists(&self, tree: &tree::Tree<Id>) -> bool {
-        let node_id = match tree.id_to_node(&self.0) {
-            Ok(v) => v,
-            Err(_) => return false,
-        };
-        let this_node = tree.nodes.get(&node_id).unwrap();
-        match &this_node.data {
-            tree::NodeData::StringSegment { ids, .. } => {
-                let pos = ids.iter().position(|(id, _)| id == &self.0).unwrap();
-                ids[pos].1.is_some()
-            }
-            tree::NodeData::String { start, .. } => true,
+        match tree.get_type(self.0.clone()) {
+            Ok(tree::NodeType::String) => true,
+            Ok(tree::NodeType::Character) => sequence::position(tree, &self.0)
+                .map(|(_, live)| live)
+                .unwrap_or(false),
             _ => false,
         }
     }
 
+    /// Returns the value embedded at this character, or `None` if it's a plain character or has
+    /// been deleted.
+    pub fn embedded(&self, tree: &tree::Tree<Id>) -> Result<Option<Value<Id>>, tree::TreeError> {
+        tree.embedded_value(&self.0)
+    }
+
     /// Returns the index that is `num` characters away from `self`. If reaches start or end of
     /// string, will stop. Takes `O(n)`; make take longer if there are a lot of deleted characters
     /// to traverse over.
@@ -241,48 +391,143 @@ impl<Id: Hash + Clone + Eq + Debug> StringIndex<Id> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ArrayRef<Id>(pub Id);
-impl<Id: Hash + Clone + Eq + Debug> ArrayRef<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> ArrayRef<Id> {
     pub fn to_vec(&self, tree: &tree::Tree<Id>) -> Result<Vec<Value<Id>>, tree::TreeError> {
-        let string_node_id = tree.id_to_node(&self.0)?;
-        let node = tree
-            .nodes
-            .get(&string_node_id)
-            .expect("node_id listed in id_to_node did not exist.");
-        let mut next = match &node.data {
+        Ok(self.iter(tree)?.map(|(_, value)| value).collect())
+    }
+
+    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
+        get_parent(tree, &self.0)
+    }
+
+    /// Returns an iterator over the `(ArrayIndex, Value)` pairs of the array's live entries, in
+    /// order. Entries are read lazily as the iterator advances.
+    pub fn iter<'a>(&self, tree: &'a tree::Tree<Id>) -> Result<ArrayIter<'a, Id>, tree::TreeError> {
+        let container = tree.id_to_node(&self.0)?;
+        let segment = match &tree.nodes[&container].data {
             tree::NodeData::Array { start, .. } => *start,
             _ => return Err(tree::TreeError::UnexpectedNodeType),
         };
-        let mut children = Vec::new();
-        while next != string_node_id {
-            let node = tree
-                .nodes
-                .get(&next)
-                .expect("node_id listed in segment adjacency did not exist.");
-            next = match &node.data {
-                tree::NodeData::ArraySegment { next, contents, .. } => {
-                    children.extend(contents.iter());
-                    *next
-                }
-                _ => panic!("debug_get_string called on non-string Id"),
-            };
+        Ok(ArrayIter::new(tree, container, segment))
+    }
+
+    fn check_type(&self, tree: &tree::Tree<Id>) -> Result<(), tree::TreeError> {
+        match tree.get_type(self.0.clone())? {
+            tree::NodeType::Array => Ok(()),
+            _ => Err(tree::TreeError::UnexpectedNodeType),
         }
-        let values = children
-            .iter()
-            .map(|child| tree.child_to_value(Some(child)))
-            .collect();
-        Ok(values)
     }
 
-    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
-        get_parent(&tree, &self.0)
+    /// Returns the number of entries in the array.
+    pub fn len(&self, tree: &tree::Tree<Id>) -> Result<usize, tree::TreeError> {
+        self.check_type(tree)?;
+        sequence::len(tree, &self.0)
+    }
+
+    pub fn is_empty(&self, tree: &tree::Tree<Id>) -> Result<bool, tree::TreeError> {
+        Ok(self.len(tree)? == 0)
+    }
+
+    /// Returns the value at position `index`, or `Value::Unset` if `index` is past the end of the
+    /// array. Takes `O(log n)`.
+    pub fn get(&self, tree: &tree::Tree<Id>, index: usize) -> Result<Value<Id>, tree::TreeError> {
+        self.check_type(tree)?;
+        let (segment, _, contents_index) = match sequence::locate(tree, &self.0, index)? {
+            Some(v) => v,
+            None => return Ok(Value::Unset),
+        };
+        match &tree.nodes[&segment].data {
+            tree::NodeData::ArraySegment { contents, .. } => {
+                Ok(tree.child_to_value(Some(&contents[contents_index])))
+            }
+            _ => panic!("node_id listed in segment index was not an array segment"),
+        }
+    }
+
+    /// Returns the id of the entry at position `index`. Takes `O(log n)`.
+    pub fn index_to_id(
+        &self,
+        tree: &tree::Tree<Id>,
+        index: usize,
+    ) -> Result<ArrayIndex<Id>, tree::TreeError> {
+        self.check_type(tree)?;
+        Ok(ArrayIndex(
+            sequence::live_ids(tree, &self.0, index, 1)?.remove(0),
+        ))
+    }
+
+    /// Returns the current position of the entry `index`, or `None` if the entry has been deleted.
+    /// Returns `TreeError::UnknownId` if `index` is not an entry of this array. Takes `O(log n)`.
+    pub fn id_to_index(
+        &self,
+        tree: &tree::Tree<Id>,
+        index: &ArrayIndex<Id>,
+    ) -> Result<Option<usize>, tree::TreeError> {
+        if index.0 == self.0 || index.parent(tree)? != *self {
+            return Err(tree::TreeError::UnknownId);
+        }
+        let (position, live) = sequence::position(tree, &index.0)?;
+        Ok(if live { Some(position) } else { None })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Iterator over the entries of an array, returned by `ArrayRef::iter`.
+pub struct ArrayIter<'a, Id: Hash + Clone + Eq + Debug + SequentialId> {
+    tree: &'a tree::Tree<Id>,
+    /// The `Array` node, which the last segment links back to.
+    container: tree::NodeId,
+    /// The segment currently being read.
+    segment: tree::NodeId,
+    /// Remaining items in the current segment.
+    items: sequence::SegmentItems<'a, Id>,
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> ArrayIter<'a, Id> {
+    fn new(tree: &'a tree::Tree<Id>, container: tree::NodeId, segment: tree::NodeId) -> Self {
+        ArrayIter {
+            tree,
+            container,
+            segment,
+            items: sequence::items(&tree.nodes[&segment]),
+        }
+    }
+}
+
+impl<'a, Id: Hash + Clone + Eq + Debug + SequentialId> Iterator for ArrayIter<'a, Id> {
+    type Item = (ArrayIndex<Id>, Value<Id>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.items.next() {
+                Some((id, Some(index))) => {
+                    let value = match &self.tree.nodes[&self.segment].data {
+                        tree::NodeData::ArraySegment { contents, .. } => {
+                            self.tree.child_to_value(Some(&contents[index]))
+                        }
+                        _ => panic!("node_id listed in segment adjacency was not an array segment"),
+                    };
+                    return Some((ArrayIndex(id), value));
+                }
+                Some((_, None)) => {}
+                None => {
+                    let tree = self.tree;
+                    let next = *tree.nodes[&self.segment].segment_adjacencies().1;
+                    if next == self.container {
+                        return None;
+                    }
+                    self.segment = next;
+                    self.items = sequence::items(&tree.nodes[&next]);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ArrayIndex<Id>(pub Id);
-impl<Id: Hash + Clone + Eq + Debug> ArrayIndex<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> ArrayIndex<Id> {
     pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<ArrayRef<Id>, tree::TreeError> {
         match tree.get_type(self.0.clone()) {
             Ok(tree::NodeType::Array) => Ok(ArrayRef(self.0.clone())),
@@ -296,19 +541,166 @@ impl<Id: Hash + Clone + Eq + Debug> ArrayIndex<Id> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// A counter, whose value is the sum of every `Edit::CounterAdd` applied to it. Unlike setting an
+/// `Int`, concurrent additions to a counter are never lost.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterRef<Id>(pub Id);
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> CounterRef<Id> {
+    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
+        get_parent(tree, &self.0)
+    }
+
+    /// Returns the current value of the counter.
+    pub fn value(&self, tree: &tree::Tree<Id>) -> Result<i64, tree::TreeError> {
+        let node_id = tree.id_to_node(&self.0)?;
+        match &tree.nodes[&node_id].data {
+            tree::NodeData::Counter { value, .. } => Ok(*value),
+            _ => Err(tree::TreeError::UnexpectedNodeType),
+        }
+    }
+}
+
+/// A set of primitive values. When one replica removes a value while another concurrently adds
+/// it, the add wins and the value stays in the set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetRef<Id>(pub Id);
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> SetRef<Id> {
+    pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
+        get_parent(tree, &self.0)
+    }
+
+    fn members<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<&'a HashMap<tree::Child, Vec<Id>>, tree::TreeError> {
+        let node_id = tree.id_to_node(&self.0)?;
+        match &tree.nodes[&node_id].data {
+            tree::NodeData::Set { members, .. } => Ok(members),
+            _ => Err(tree::TreeError::UnexpectedNodeType),
+        }
+    }
+
+    /// Returns whether `value` is in the set. Returns `UnsupportedValue` if `value` is a
+    /// collection or unset, since those can never be members.
+    pub fn contains(
+        &self,
+        tree: &tree::Tree<Id>,
+        value: &Value<Id>,
+    ) -> Result<bool, tree::TreeError> {
+        let member = tree.set_member(value)?;
+        Ok(self.members(tree)?.contains_key(&member))
+    }
+
+    /// Returns the number of values in the set.
+    pub fn len(&self, tree: &tree::Tree<Id>) -> Result<usize, tree::TreeError> {
+        Ok(self.members(tree)?.len())
+    }
+
+    pub fn is_empty(&self, tree: &tree::Tree<Id>) -> Result<bool, tree::TreeError> {
+        Ok(self.members(tree)?.is_empty())
+    }
+
+    /// Returns an iterator over the values in the set, so that the order is the same on every
+    /// replica: `Null` first, then `False`, `True`, integers, floats and bytes, with numbers in
+    /// ascending order and bytes in lexicographic order. Takes `O(n log n)` to start iterating.
+    pub fn iter<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<impl Iterator<Item = Value<Id>> + 'a, tree::TreeError> {
+        let mut members: Vec<&tree::Child> = self.members(tree)?.keys().collect();
+        members.sort_by(|a, b| member_order(a, b));
+        Ok(members
+            .into_iter()
+            .map(move |child| tree.child_to_value(Some(child))))
+    }
+}
+
+fn member_order(a: &tree::Child, b: &tree::Child) -> Ordering {
+    fn rank(child: &tree::Child) -> u8 {
+        match child {
+            tree::Child::Null => 0,
+            tree::Child::False => 1,
+            tree::Child::True => 2,
+            tree::Child::Int(_) => 3,
+            tree::Child::Float(_) => 4,
+            tree::Child::Bytes(_) => 5,
+            tree::Child::Collection(_) => 6,
+        }
+    }
+    match (a, b) {
+        (tree::Child::Int(a), tree::Child::Int(b)) => a.cmp(b),
+        (tree::Child::Float(a), tree::Child::Float(b)) => {
+            f64::from_bits(*a).total_cmp(&f64::from_bits(*b))
+        }
+        (tree::Child::Bytes(a), tree::Child::Bytes(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ObjectRef<Id>(pub Id);
-impl<Id: Hash + Clone + Eq + Debug> ObjectRef<Id> {
+impl<Id: Hash + Clone + Eq + Debug + SequentialId> ObjectRef<Id> {
     pub fn parent(&self, tree: &tree::Tree<Id>) -> Result<Parent<Id>, tree::TreeError> {
-        get_parent(&tree, &self.0)
+        get_parent(tree, &self.0)
     }
 
-    pub fn get(&self, tree: &tree::Tree<Id>, key: &str) -> Result<Value<Id>, tree::TreeError> {
+    fn items<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<&'a HashMap<String, tree::Child>, tree::TreeError> {
         let object_node_id = tree.id_to_node(&self.0)?;
-        let child = match &tree.nodes[&object_node_id].data {
-            tree::NodeData::Object { items, id: _ } => items.get(key),
-            _ => return Err(tree::TreeError::UnexpectedNodeType),
-        };
-        Ok(tree.child_to_value(child))
+        match &tree.nodes[&object_node_id].data {
+            tree::NodeData::Object { items, .. } => Ok(items),
+            _ => Err(tree::TreeError::UnexpectedNodeType),
+        }
+    }
+
+    pub fn get(&self, tree: &tree::Tree<Id>, key: &str) -> Result<Value<Id>, tree::TreeError> {
+        Ok(tree.child_to_value(self.items(tree)?.get(key)))
+    }
+
+    /// Returns every value of `key`. This has more than one value if the key was set by
+    /// concurrent `Edit::MapInsertMulti` writes that haven't been resolved, in which case `get`
+    /// returns the last of them. Returns an empty `Vec` if the key is unset.
+    pub fn get_all(
+        &self,
+        tree: &tree::Tree<Id>,
+        key: &str,
+    ) -> Result<Vec<Value<Id>>, tree::TreeError> {
+        tree.object_values(&self.0, key)
+    }
+
+    /// Returns the number of keys in the object.
+    pub fn len(&self, tree: &tree::Tree<Id>) -> Result<usize, tree::TreeError> {
+        Ok(self.items(tree)?.len())
+    }
+
+    pub fn is_empty(&self, tree: &tree::Tree<Id>) -> Result<bool, tree::TreeError> {
+        Ok(self.items(tree)?.is_empty())
+    }
+
+    pub fn contains_key(&self, tree: &tree::Tree<Id>, key: &str) -> Result<bool, tree::TreeError> {
+        Ok(self.items(tree)?.contains_key(key))
+    }
+
+    /// Returns the keys of the object in sorted order, so that the order is the same on every
+    /// replica. Takes `O(n log n)`.
+    pub fn keys(&self, tree: &tree::Tree<Id>) -> Result<Vec<String>, tree::TreeError> {
+        let mut keys: Vec<String> = self.items(tree)?.keys().cloned().collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Returns an iterator over the object's `(key, value)` pairs, sorted by key. Takes
+    /// `O(n log n)` to start iterating.
+    pub fn entries<'a>(
+        &self,
+        tree: &'a tree::Tree<Id>,
+    ) -> Result<impl Iterator<Item = (String, Value<Id>)> + 'a, tree::TreeError> {
+        let mut items: Vec<&(String, tree::Child)> = self.items(tree)?.iter().collect();
+        items.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(items
+            .into_iter()
+            .map(move |(key, child)| (key.clone(), tree.child_to_value(Some(child)))))
     }
 }
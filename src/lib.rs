@@ -1,5 +1,8 @@
 pub mod json;
 pub mod opset;
+pub mod wire;
 
 mod doc;
 pub use doc::*;
+mod transaction;
+pub use transaction::*;
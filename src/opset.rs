@@ -1,55 +1,115 @@
 pub trait Operation<State> {
-    fn apply(&self, tree: &mut State);
+    type Error;
+
+    /// Applies this op to `state`. If the op fails, `state` should be left as it was before
+    /// `apply` was called.
+    fn apply(&self, state: &mut State) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpsetError {
+    /// An op compared equal to an op already in the set, but had different contents.
+    ConflictingOp,
+}
+
+/// Describes the effects of a call to `Opset::update` or `Opset::update_from_iter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateResult<E, Err> {
+    /// Result of inserting each op, in the order they were passed in. `Ok(true)` means the op was
+    /// newly applied, and `Ok(false)` means an identical op was already in the set, in which case
+    /// it was ignored.
+    pub inserted: Vec<Result<bool, OpsetError>>,
+    /// Ops that failed while being applied during this update, and had not previously failed with
+    /// the same error. Besides newly inserted ops, this may include existing ops that now fail
+    /// because an earlier op was inserted before them.
+    pub failures: Vec<(E, Err)>,
 }
 
 pub struct Opset<E: Operation<S> + Ord, S: Clone> {
     /// list of all ops applied to this tree
     ops: Vec<E>,
+    /// result of applying each op in `ops`. `None` for ops that have been inserted but not yet
+    /// applied.
+    results: Vec<Option<Result<(), E::Error>>>,
     /// a list of (num ops applied, state of tree at that point in time)
     states: Vec<(usize, S)>,
     /// at most, this many ops will be skipped over in the states cache
     cache_gap: usize,
 }
 
-impl<E: Operation<S> + Ord, S: Clone> Opset<E, S> {
+impl<E, S> Opset<E, S>
+where
+    E: Operation<S> + Ord + Clone,
+    E::Error: Clone + PartialEq,
+    S: Clone,
+{
     pub fn new(initial_state: S, cache_gap: usize) -> Self {
         Opset {
             ops: Vec::new(),
+            results: Vec::new(),
             cache_gap,
             states: vec![(0, initial_state)],
         }
     }
 
-    pub fn update(&mut self, edit: E) {
-        let insert_point = self
-            .ops
-            .binary_search(&edit)
-            .expect_err("two ops had the same timestamp");
-        self.ops.insert(insert_point, edit);
-        self.recalculate(insert_point);
+    /// Inserts `op` into the set, recalculating any states that come after it. If an identical op
+    /// was already in the set, nothing changes. If an op that compares equal to `op` but has
+    /// different contents is already in the set, `op` is rejected with
+    /// `OpsetError::ConflictingOp`.
+    pub fn update(&mut self, op: E) -> UpdateResult<E, E::Error> {
+        self.update_from_iter(std::iter::once(op))
     }
 
-    pub fn update_from_iter<I: std::iter::Iterator<Item = E>>(&mut self, ops: I) {
+    /// Like `update`, but only recalculates states once for the whole batch.
+    pub fn update_from_iter<I: std::iter::Iterator<Item = E>>(
+        &mut self,
+        ops: I,
+    ) -> UpdateResult<E, E::Error> {
         let mut least_insert_point = None;
-        for edit in ops {
-            let insert_point = self
-                .ops
-                .binary_search(&edit)
-                .expect_err("two ops had the same timestamp");
-            self.ops.insert(insert_point, edit);
+        let mut inserted = Vec::new();
+        for op in ops {
+            let insert_point = match self.insert(op) {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    inserted.push(Ok(false));
+                    continue;
+                }
+                Err(e) => {
+                    inserted.push(Err(e));
+                    continue;
+                }
+            };
+            inserted.push(Ok(true));
             least_insert_point = match least_insert_point {
                 Some(prev) if prev < insert_point => Some(prev),
                 _ => Some(insert_point),
             };
         }
-        if let Some(least_insert_point) = least_insert_point {
-            self.recalculate(least_insert_point);
+        let failures = match least_insert_point {
+            Some(least_insert_point) => self.recalculate(least_insert_point),
+            None => Vec::new(),
+        };
+        UpdateResult { inserted, failures }
+    }
+
+    /// Inserts `op` into the op list without recalculating states, returning the index it was
+    /// inserted at, or `None` if it was an exact duplicate of an existing op.
+    fn insert(&mut self, op: E) -> Result<Option<usize>, OpsetError> {
+        match self.ops.binary_search(&op) {
+            Ok(existing) if self.ops[existing] == op => Ok(None),
+            Ok(_) => Err(OpsetError::ConflictingOp),
+            Err(insert_point) => {
+                self.ops.insert(insert_point, op);
+                self.results.insert(insert_point, None);
+                Ok(Some(insert_point))
+            }
         }
     }
 
     /// Recalculates states after the edit list has been changed. The first `insert_point`
-    /// ops should be identical to the last time `recalculate` was called.
-    fn recalculate(&mut self, insert_point: usize) {
+    /// ops should be identical to the last time `recalculate` was called. Returns the ops that
+    /// failed to apply, and did not previously fail with the same error.
+    fn recalculate(&mut self, insert_point: usize) -> Vec<(E, E::Error)> {
         let index_of_first_bad_state =
             match self.states.binary_search_by_key(&insert_point, |(n, _)| *n) {
                 Ok(n) => n + 1,
@@ -59,17 +119,46 @@ impl<E: Operation<S> + Ord, S: Clone> Opset<E, S> {
         // exactly at `least_insert_point` it is preserved.
         self.states.truncate(index_of_first_bad_state);
         let (mut applied_ops, mut state) = self.states.pop().unwrap();
+        let mut failures = Vec::new();
         while applied_ops < self.ops.len() {
-            if self.states.len() == 0
+            if self.states.is_empty()
                 || self.states.last().unwrap().0 + self.cache_gap <= applied_ops
             {
                 // time to insert a new cache
                 self.states.push((applied_ops, state.clone()));
             }
-            self.ops[applied_ops].apply(&mut state);
+            let result = self.ops[applied_ops].apply(&mut state);
+            if let Err(e) = &result {
+                if self.results[applied_ops].as_ref() != Some(&result) {
+                    failures.push((self.ops[applied_ops].clone(), e.clone()));
+                }
+            }
+            self.results[applied_ops] = Some(result);
             applied_ops += 1;
         }
         self.states.push((applied_ops, state));
+        failures
+    }
+
+    /// Returns every op in the set that failed to apply, along with its error, in op order.
+    pub fn failures(&self) -> impl Iterator<Item = (&E, &E::Error)> {
+        self.ops
+            .iter()
+            .zip(self.results.iter())
+            .filter_map(|(op, result)| match result {
+                Some(Err(e)) => Some((op, e)),
+                _ => None,
+            })
+    }
+
+    /// If `op` is in the set and failed to apply, returns the error it failed with. A different
+    /// op that merely compares equal to `op` in the set's ordering doesn't count.
+    pub fn failure(&self, op: &E) -> Option<&E::Error> {
+        let index = self.ops.binary_search(op).ok()?;
+        if self.ops[index] != *op {
+            return None;
+        }
+        self.results[index].as_ref()?.as_ref().err()
     }
 
     pub fn state(&self) -> &S {
@@ -85,15 +174,34 @@ impl<E: Operation<S> + Ord, S: Clone> Opset<E, S> {
 mod test {
     use super::*;
 
-    #[derive(PartialOrd, Ord, Debug, Clone, Eq, PartialEq)]
+    #[derive(Debug, Clone, Eq, PartialEq)]
     struct TestEdit {
         timestamp: usize,
         value: usize,
     }
 
+    impl PartialOrd for TestEdit {
+        fn partial_cmp(&self, other: &TestEdit) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for TestEdit {
+        fn cmp(&self, other: &TestEdit) -> std::cmp::Ordering {
+            self.timestamp.cmp(&other.timestamp)
+        }
+    }
+
     impl Operation<Vec<usize>> for TestEdit {
-        fn apply(&self, state: &mut Vec<usize>) {
+        type Error = usize;
+
+        /// Values of 100 or more fail to apply.
+        fn apply(&self, state: &mut Vec<usize>) -> Result<(), usize> {
+            if self.value >= 100 {
+                return Err(self.value);
+            }
             state.push(self.value);
+            Ok(())
         }
     }
 
@@ -171,4 +279,132 @@ mod test {
         assert_eq!(crdt.state(), &[0, 2, 1, 5, 4, 3]);
         assert_eq!(crdt.states.len(), 4);
     }
+
+    #[test]
+    fn duplicate_ops_are_ignored() {
+        let mut crdt = Opset::new(vec![0], 2);
+        let op = TestEdit {
+            timestamp: 10,
+            value: 1,
+        };
+        assert_eq!(crdt.update(op.clone()).inserted, vec![Ok(true)]);
+        assert_eq!(crdt.update(op.clone()).inserted, vec![Ok(false)]);
+        assert_eq!(crdt.state(), &[0, 1]);
+
+        let results = crdt.update_from_iter(
+            vec![
+                op.clone(),
+                TestEdit {
+                    timestamp: 5,
+                    value: 2,
+                },
+                TestEdit {
+                    timestamp: 5,
+                    value: 2,
+                },
+            ]
+            .into_iter(),
+        );
+        assert_eq!(results.inserted, vec![Ok(false), Ok(true), Ok(false)]);
+        assert_eq!(crdt.state(), &[0, 2, 1]);
+    }
+
+    #[test]
+    fn conflicting_ops_error() {
+        let mut crdt = Opset::new(vec![0], 2);
+        crdt.update(TestEdit {
+            timestamp: 10,
+            value: 1,
+        });
+        assert_eq!(
+            crdt.update(TestEdit {
+                timestamp: 10,
+                value: 2,
+            })
+            .inserted,
+            vec![Err(OpsetError::ConflictingOp)]
+        );
+        let results = crdt.update_from_iter(
+            vec![
+                TestEdit {
+                    timestamp: 10,
+                    value: 3,
+                },
+                TestEdit {
+                    timestamp: 15,
+                    value: 4,
+                },
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            results.inserted,
+            vec![Err(OpsetError::ConflictingOp), Ok(true)]
+        );
+        assert_eq!(crdt.state(), &[0, 1, 4]);
+    }
+
+    #[test]
+    fn failures_are_recorded() {
+        let mut crdt = Opset::new(vec![0], 2);
+        let ops = vec![
+            TestEdit {
+                timestamp: 10,
+                value: 1,
+            },
+            TestEdit {
+                timestamp: 20,
+                value: 200,
+            },
+            TestEdit {
+                timestamp: 30,
+                value: 3,
+            },
+        ];
+        let results = crdt.update_from_iter(ops.clone().into_iter());
+        assert_eq!(results.failures, vec![(ops[1].clone(), 200)]);
+        assert_eq!(crdt.state(), &[0, 1, 3]);
+        assert_eq!(crdt.failure(&ops[0]), None);
+        assert_eq!(crdt.failure(&ops[1]), Some(&200));
+        assert_eq!(crdt.failure(&ops[2]), None);
+        // an op with the same timestamp as a failed op, but different contents, isn't in the set
+        assert_eq!(
+            crdt.failure(&TestEdit {
+                timestamp: 20,
+                value: 300,
+            }),
+            None
+        );
+
+        // inserting an op early recalculates results for later ops, but only reports new failures
+        let results = crdt.update(TestEdit {
+            timestamp: 5,
+            value: 500,
+        });
+        assert_eq!(
+            results.failures,
+            vec![(
+                TestEdit {
+                    timestamp: 5,
+                    value: 500,
+                },
+                500
+            )]
+        );
+        assert_eq!(crdt.state(), &[0, 1, 3]);
+        assert_eq!(crdt.failure(&ops[1]), Some(&200));
+        assert_eq!(
+            crdt.failure(&TestEdit {
+                timestamp: 5,
+                value: 500,
+            }),
+            Some(&500)
+        );
+        assert_eq!(
+            crdt.failures()
+                .map(|(op, e)| (op.timestamp, *e))
+                .collect::<Vec<_>>(),
+            vec![(5, 500), (20, 200)]
+        );
+    }
 }
@@ -0,0 +1,773 @@
+use crate::doc::{Doc, DocOp, Id};
+use crate::json::{
+    self, ArrayIndex, ArrayRef, CounterRef, Edit, IdRange, ObjectRef, SetRef, StringRef, TreeError,
+    Value,
+};
+use crate::opset::OpsetError;
+
+/// Why `Doc::transact` couldn't produce an op.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactError {
+    /// One of the transaction's edits couldn't be applied to the document.
+    Tree(TreeError),
+    /// The op couldn't be added to the document's opset, such as when a different op already has
+    /// its timestamp because another replica is using this replica's site id.
+    Opset(OpsetError),
+    /// This replica's clock has run out of timestamp counters.
+    ClockOverflow,
+}
+
+impl From<TreeError> for TransactError {
+    fn from(error: TreeError) -> Self {
+        TransactError::Tree(error)
+    }
+}
+
+/// Records a batch of local edits to a `Doc`. Created by `Doc::transact`.
+///
+/// Every method allocates whatever ids it needs and immediately applies its edits to a working
+/// copy of the document, so later calls in the same transaction observe earlier ones. Nothing is
+/// applied to the `Doc` itself until the transaction finishes successfully.
+pub struct Transaction<'a> {
+    doc: &'a mut Doc,
+    /// The document's tree with all of this transaction's edits applied so far.
+    tree: json::Tree<Id>,
+    edits: Vec<Edit<Id>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(doc: &'a mut Doc) -> Transaction<'a> {
+        Transaction {
+            tree: doc.tree().clone(),
+            doc,
+            edits: Vec::new(),
+        }
+    }
+
+    pub(crate) fn into_edits(self) -> Vec<Edit<Id>> {
+        self.edits
+    }
+
+    /// The document as it looks with this transaction's edits applied so far.
+    pub fn tree(&self) -> &json::Tree<Id> {
+        &self.tree
+    }
+
+    fn next_id(&mut self) -> Result<Id, TreeError> {
+        self.next_id_run(1)
+    }
+
+    fn next_id_run(&mut self, len: usize) -> Result<Id, TreeError> {
+        self.doc.try_next_id_run(len).ok_or(TreeError::IdOverflow)
+    }
+
+    /// Applies `edit` to the working copy and records it. If the edit fails, the working copy is
+    /// left as it was, so an error from one call doesn't leave later calls with a half-applied
+    /// edit.
+    fn apply(&mut self, edit: Edit<Id>) -> Result<(), TreeError> {
+        self.tree.atomically(|tree| tree.update(&edit))?;
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Creates a new, empty object. The object has no parent until it is placed with `set`,
+    /// `push` or `insert`.
+    pub fn create_object(&mut self) -> Result<ObjectRef<Id>, TreeError> {
+        let id = ObjectRef(self.next_id()?);
+        self.apply(Edit::MapCreate { id: id.clone() })?;
+        Ok(id)
+    }
+
+    /// Creates a new, empty array. The array has no parent until it is placed with `set`, `push`
+    /// or `insert`.
+    pub fn create_array(&mut self) -> Result<ArrayRef<Id>, TreeError> {
+        let id = ArrayRef(self.next_id()?);
+        self.apply(Edit::ArrayCreate { id: id.clone() })?;
+        Ok(id)
+    }
+
+    /// Creates a new, empty string. The string has no parent until it is placed with `set`,
+    /// `push` or `insert`.
+    pub fn create_string(&mut self) -> Result<StringRef<Id>, TreeError> {
+        let id = StringRef(self.next_id()?);
+        self.apply(Edit::TextCreate { id: id.clone() })?;
+        Ok(id)
+    }
+
+    /// Creates a new counter with a value of zero. The counter has no parent until it is placed
+    /// with `set`, `push` or `insert`.
+    pub fn create_counter(&mut self) -> Result<CounterRef<Id>, TreeError> {
+        let id = CounterRef(self.next_id()?);
+        self.apply(Edit::CounterCreate { id: id.clone() })?;
+        Ok(id)
+    }
+
+    /// Adds `amount` to `counter`. Additions made concurrently on other replicas are all kept.
+    pub fn increment(&mut self, counter: &CounterRef<Id>, amount: i64) -> Result<(), TreeError> {
+        self.apply(Edit::CounterAdd {
+            id: counter.clone(),
+            amount,
+        })
+    }
+
+    /// Creates a new empty set. The set has no parent until it is placed with `set`, `push` or
+    /// `insert`.
+    pub fn create_set(&mut self) -> Result<SetRef<Id>, TreeError> {
+        let id = SetRef(self.next_id()?);
+        self.apply(Edit::SetCreate { id: id.clone() })?;
+        Ok(id)
+    }
+
+    /// Adds `value` to `set`. Only `Null`, `True`, `False`, `Int`, `Float` and `Bytes` can be
+    /// added.
+    pub fn add_member(&mut self, set: &SetRef<Id>, value: Value<Id>) -> Result<(), TreeError> {
+        let tag = self.next_id()?;
+        self.apply(Edit::SetAdd {
+            id: set.clone(),
+            tag,
+            item: value,
+        })
+    }
+
+    /// Removes `value` from `set`, if it's there. If another replica adds `value` concurrently,
+    /// their add wins and `value` stays in the set.
+    pub fn remove_member(&mut self, set: &SetRef<Id>, value: &Value<Id>) -> Result<(), TreeError> {
+        let observed = self.tree.set_tags(&set.0, value)?;
+        if observed.is_empty() {
+            return Ok(());
+        }
+        self.apply(Edit::SetRemove {
+            id: set.clone(),
+            item: value.clone(),
+            observed,
+        })
+    }
+
+    /// Sets `object[key]` to `value`. Setting `Value::Unset` removes the key.
+    pub fn set(
+        &mut self,
+        object: &ObjectRef<Id>,
+        key: &str,
+        value: Value<Id>,
+    ) -> Result<(), TreeError> {
+        self.apply(Edit::MapInsert {
+            parent: object.clone(),
+            key: key.to_string(),
+            item: value,
+        })
+    }
+
+    /// Sets `object[key]` to `value` with an `Edit::MapInsertMulti`, replacing every value of
+    /// the key that this replica has seen. Writes made concurrently on other replicas are kept
+    /// alongside this one, and can be read with `ObjectRef::get_all`. Calling this again resolves
+    /// the conflict. Setting `Value::Unset` removes the values without adding a new one.
+    pub fn set_multi(
+        &mut self,
+        object: &ObjectRef<Id>,
+        key: &str,
+        value: Value<Id>,
+    ) -> Result<(), TreeError> {
+        let supersedes = self.tree.object_write_ids(&object.0, key)?;
+        let id = self.next_id()?;
+        self.apply(Edit::MapInsertMulti {
+            parent: object.clone(),
+            key: key.to_string(),
+            id,
+            item: value,
+            supersedes,
+        })
+    }
+
+    /// Inserts `text` into `string` so that its first character ends up at character offset
+    /// `pos`. The whole of `text` is inserted with a single `Edit::TextInsertRun`.
+    pub fn insert_text(
+        &mut self,
+        string: &StringRef<Id>,
+        pos: usize,
+        text: &str,
+    ) -> Result<(), TreeError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let index = string.index_at(&self.tree, pos)?;
+        let id = self.next_id_run(text.chars().count())?;
+        self.apply(Edit::TextInsertRun {
+            index,
+            id: json::StringIndex(id),
+            text: text.to_string(),
+        })
+    }
+
+    /// Embeds `value` in `string` at character offset `pos`, returning the index of the
+    /// placeholder character that holds its place. The embed is removed by deleting that
+    /// character, like any other.
+    pub fn insert_embed(
+        &mut self,
+        string: &StringRef<Id>,
+        pos: usize,
+        value: Value<Id>,
+    ) -> Result<json::StringIndex<Id>, TreeError> {
+        let index = string.index_at(&self.tree, pos)?;
+        let id = json::StringIndex(self.next_id()?);
+        self.apply(Edit::TextInsertEmbed {
+            index,
+            id: id.clone(),
+            item: value,
+        })?;
+        Ok(id)
+    }
+
+    /// Deletes the `len` characters of `string` starting at character offset `pos`, with a
+    /// single `Edit::TextDeleteRange`. The edit lists the characters it deletes, so concurrent
+    /// inserts inside the range survive.
+    pub fn delete_text(
+        &mut self,
+        string: &StringRef<Id>,
+        pos: usize,
+        len: usize,
+    ) -> Result<(), TreeError> {
+        let ids = self.tree.sequence_ids(&string.0, pos, len)?;
+        if !ids.is_empty() {
+            self.apply(Edit::TextDeleteRange {
+                ids: IdRange::from_ids(ids),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Sets the formatting attribute `name` to `value` on the `len` characters starting at
+    /// character offset `pos` in `string`, with an `Edit::TextMark`. A null `value` removes the
+    /// attribute. `expand` controls whether text later inserted at either edge of the range is
+    /// formatted too.
+    pub fn mark_text(
+        &mut self,
+        string: &StringRef<Id>,
+        pos: usize,
+        len: usize,
+        name: &str,
+        value: serde_json::Value,
+        expand: json::Expand,
+    ) -> Result<(), TreeError> {
+        let (first, last) = match self.range_ends(&string.0, pos, len)? {
+            Some(ends) => ends,
+            None => return Ok(()),
+        };
+        let start = match expand {
+            json::Expand::Before | json::Expand::Both => string.index_at(&self.tree, pos)?,
+            json::Expand::None | json::Expand::After => json::StringIndex(first),
+        };
+        let end = match expand {
+            json::Expand::After | json::Expand::Both => {
+                if pos + len == self.tree.sequence_len(&string.0)? {
+                    // the range runs to the end of the string
+                    json::StringIndex(string.0.clone())
+                } else {
+                    json::StringIndex(self.tree.sequence_ids(&string.0, pos + len, 1)?.remove(0))
+                }
+            }
+            json::Expand::None | json::Expand::Before => json::StringIndex(last),
+        };
+        self.apply(Edit::TextMark {
+            start,
+            end,
+            expand,
+            name: name.to_string(),
+            value,
+        })
+    }
+
+    /// Returns the ids of the first and last of the `len` items starting at position `pos` in
+    /// `sequence`, or `None` if `len` is zero.
+    fn range_ends(
+        &self,
+        sequence: &Id,
+        pos: usize,
+        len: usize,
+    ) -> Result<Option<(Id, Id)>, TreeError> {
+        let end_pos = match pos.checked_add(len) {
+            Some(end_pos) if end_pos <= self.tree.sequence_len(sequence)? => end_pos,
+            _ => return Err(TreeError::IndexOutOfBounds),
+        };
+        if len == 0 {
+            return Ok(None);
+        }
+        let start = self.tree.sequence_ids(sequence, pos, 1)?.remove(0);
+        let end = self.tree.sequence_ids(sequence, end_pos - 1, 1)?.remove(0);
+        Ok(Some((start, end)))
+    }
+
+    /// Appends `value` to the end of `array`, returning the id of the new entry.
+    pub fn push(
+        &mut self,
+        array: &ArrayRef<Id>,
+        value: Value<Id>,
+    ) -> Result<ArrayIndex<Id>, TreeError> {
+        let len = self.tree.sequence_len(&array.0)?;
+        self.insert(array, len, value)
+    }
+
+    /// Inserts `value` into `array` at position `index`, returning the id of the new entry.
+    pub fn insert(
+        &mut self,
+        array: &ArrayRef<Id>,
+        index: usize,
+        value: Value<Id>,
+    ) -> Result<ArrayIndex<Id>, TreeError> {
+        let prev = self.tree.sequence_index_at(&array.0, index)?;
+        let id = ArrayIndex(self.next_id()?);
+        self.apply(Edit::ArrayInsert {
+            index: ArrayIndex(prev),
+            id: id.clone(),
+            item: value,
+        })?;
+        Ok(id)
+    }
+
+    /// Builds the JSON value `json` out of newly created values, returning the root of the
+    /// result. Like other newly created values, it has no parent until placed with `set`, `push`
+    /// or `insert`.
+    pub fn import_json(&mut self, json: &serde_json::Value) -> Result<Value<Id>, TreeError> {
+        Ok(match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(true) => Value::True,
+            serde_json::Value::Bool(false) => Value::False,
+            serde_json::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => Value::Int(i),
+                // integers too large for an `i64` would lose precision as a float
+                (None, Some(f)) if n.is_f64() => Value::Float(f),
+                _ => return Err(TreeError::UnsupportedValue),
+            },
+            serde_json::Value::String(s) => {
+                let string = self.create_string()?;
+                self.insert_text(&string, 0, s)?;
+                Value::String(string)
+            }
+            serde_json::Value::Array(items) => {
+                let array = self.create_array()?;
+                let mut prev = array.0.clone();
+                for item in items {
+                    let item = self.import_json(item)?;
+                    let id = ArrayIndex(self.next_id()?);
+                    self.apply(Edit::ArrayInsert {
+                        index: ArrayIndex(prev),
+                        id: id.clone(),
+                        item,
+                    })?;
+                    prev = id.0;
+                }
+                Value::Array(array)
+            }
+            serde_json::Value::Object(items) => {
+                let object = self.create_object()?;
+                for (key, item) in items {
+                    let item = self.import_json(item)?;
+                    self.set(&object, key, item)?;
+                }
+                Value::Object(object)
+            }
+        })
+    }
+
+    /// Removes the entry at position `index` from `array`.
+    pub fn remove(&mut self, array: &ArrayRef<Id>, index: usize) -> Result<(), TreeError> {
+        let id = self.tree.sequence_ids(&array.0, index, 1)?.remove(0);
+        self.apply(Edit::ArrayDelete { id: ArrayIndex(id) })
+    }
+
+    /// Moves the entry at position `from_index` in `from` so that it ends up at position
+    /// `to_index` in `to`, which may be the same array. Returns the entry's id at its new
+    /// position.
+    pub fn move_entry(
+        &mut self,
+        from: &ArrayRef<Id>,
+        from_index: usize,
+        to: &ArrayRef<Id>,
+        to_index: usize,
+    ) -> Result<ArrayIndex<Id>, TreeError> {
+        let id = self.tree.sequence_ids(&from.0, from_index, 1)?.remove(0);
+        // positions in `to` after the entry is removed are one further along while it's still there
+        let anchor_pos = if from == to && to_index >= from_index {
+            to_index + 1
+        } else {
+            to_index
+        };
+        let prev = self.tree.sequence_index_at(&to.0, anchor_pos)?;
+        let new_id = ArrayIndex(self.next_id()?);
+        self.apply(Edit::ArrayMove {
+            id: ArrayIndex(id),
+            index: ArrayIndex(prev),
+            new_id: new_id.clone(),
+        })?;
+        Ok(new_id)
+    }
+
+    /// Removes the `len` entries of `array` starting at position `index`, with a single
+    /// `Edit::ArrayDeleteRange`. Like `delete_text`, concurrent inserts inside the range survive.
+    pub fn remove_range(
+        &mut self,
+        array: &ArrayRef<Id>,
+        index: usize,
+        len: usize,
+    ) -> Result<(), TreeError> {
+        let ids = self.tree.sequence_ids(&array.0, index, len)?;
+        if !ids.is_empty() {
+            self.apply(Edit::ArrayDeleteRange {
+                ids: IdRange::from_ids(ids),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Doc {
+    /// Creates a single op that sets `object[key]` to a copy of the JSON value `json`.
+    pub fn set_json(
+        &mut self,
+        object: &ObjectRef<Id>,
+        key: &str,
+        json: &serde_json::Value,
+    ) -> Result<DocOp, TransactError> {
+        self.transact(|tx| {
+            let value = tx.import_json(json)?;
+            tx.set(object, key, value)
+        })
+    }
+
+    /// Creates a single op that inserts a copy of the JSON value `json` into `array` at position
+    /// `index`.
+    pub fn insert_json(
+        &mut self,
+        array: &ArrayRef<Id>,
+        index: usize,
+        json: &serde_json::Value,
+    ) -> Result<DocOp, TransactError> {
+        self.transact(|tx| {
+            let value = tx.import_json(json)?;
+            tx.insert(array, index, value).map(|_| ())
+        })
+    }
+
+    /// Runs `f` to build up a batch of local edits, applies them to this document as a single
+    /// `DocOp`, and returns that op so it can be sent to other replicas. If `f` returns an error,
+    /// the document is left unchanged.
+    ///
+    /// If the finished op still fails to apply to the document, returns `TransactError::Tree` with
+    /// the error of the edit that failed, or `TransactError::Opset` if the opset refused the op.
+    /// The op is not returned in that case, since it has no effect on the document.
+    pub fn transact<F>(&mut self, f: F) -> Result<DocOp, TransactError>
+    where
+        F: FnOnce(&mut Transaction) -> Result<(), TreeError>,
+    {
+        let mut tx = Transaction::new(self);
+        f(&mut tx)?;
+        let edits = tx.into_edits();
+        let op = DocOp {
+            timestamp: self
+                .try_next_timestamp()
+                .ok_or(TransactError::ClockOverflow)?,
+            edits,
+        };
+        let report = self.update(op.clone());
+        if let Some((_, failure)) = report
+            .failures
+            .into_iter()
+            .find(|(t, _)| *t == op.timestamp)
+        {
+            return Err(TransactError::Tree(failure.error));
+        }
+        if !report.applied.contains(&op.timestamp) {
+            return Err(TransactError::Opset(OpsetError::ConflictingOp));
+        }
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TransactError;
+    use crate::doc::*;
+    use crate::json::{self, ObjectRef, TreeError, Value};
+
+    fn string_at(doc: &Doc, key: &str) -> String {
+        match ObjectRef(ROOT_ID).get(doc.tree(), key).unwrap() {
+            Value::String(s) => s.to_string(doc.tree()).unwrap(),
+            v => panic!("expected string, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn transact_edits_text() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let mut string = None;
+        let first = doc
+            .transact(|tx| {
+                let s = tx.create_string()?;
+                tx.set(&ObjectRef(ROOT_ID), "text", Value::String(s.clone()))?;
+                tx.insert_text(&s, 0, "hello")?;
+                tx.insert_text(&s, 5, " world")?;
+                string = Some(s);
+                Ok(())
+            })
+            .unwrap();
+        // each insert_text call is a single run of characters
+        assert_eq!(first.edits.len(), 4);
+        assert_eq!(string_at(&doc, "text"), "hello world");
+
+        let string = string.unwrap();
+        let op = doc
+            .transact(|tx| {
+                tx.delete_text(&string, 0, 6)?;
+                tx.insert_text(&string, 5, "!")
+            })
+            .unwrap();
+        assert_eq!(op.edits.len(), 2);
+        assert_eq!(string_at(&doc, "text"), "world!");
+
+        // the op can be replayed on another replica
+        let mut other = Doc::new_with_site(SiteId([2; 16]));
+        other.update_from_iter(vec![op, first].into_iter());
+        assert_eq!(string_at(&other, "text"), "world!");
+    }
+
+    #[test]
+    fn transact_edits_arrays() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        doc.transact(|tx| {
+            let array = tx.create_array()?;
+            tx.set(&ObjectRef(ROOT_ID), "list", Value::Array(array.clone()))?;
+            tx.push(&array, Value::Int(2))?;
+            tx.insert(&array, 0, Value::Int(1))?;
+            tx.push(&array, Value::Int(3))?;
+            let object = tx.create_object()?;
+            tx.insert(&array, 3, Value::Object(object.clone()))?;
+            tx.set(&object, "nested", Value::True)?;
+            tx.remove(&array, 1)
+        })
+        .unwrap();
+
+        let array = match ObjectRef(ROOT_ID).get(doc.tree(), "list").unwrap() {
+            Value::Array(a) => a,
+            v => panic!("expected array, got {:?}", v),
+        };
+        let values = array.to_vec(doc.tree()).unwrap();
+        assert_eq!(values[..2], [Value::Int(1), Value::Int(3)]);
+        match &values[2] {
+            Value::Object(object) => {
+                assert_eq!(object.get(doc.tree(), "nested"), Ok(Value::True));
+            }
+            v => panic!("expected object, got {:?}", v),
+        }
+
+        let op = doc
+            .transact(|tx| {
+                assert_eq!(
+                    tx.remove_range(&array, 1, 3),
+                    Err(TreeError::IndexOutOfBounds)
+                );
+                assert_eq!(
+                    tx.remove_range(&array, 1, usize::MAX),
+                    Err(TreeError::IndexOutOfBounds)
+                );
+                tx.remove_range(&array, 0, 0)?;
+                tx.remove_range(&array, 0, 2)
+            })
+            .unwrap();
+        assert_eq!(op.edits.len(), 1);
+        assert_eq!(array.len(doc.tree()), Ok(1));
+        assert!(matches!(array.get(doc.tree(), 0), Ok(Value::Object(_))));
+    }
+
+    fn array_at(doc: &Doc, key: &str) -> json::ArrayRef<Id> {
+        match ObjectRef(ROOT_ID).get(doc.tree(), key).unwrap() {
+            Value::Array(a) => a,
+            v => panic!("expected array, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn transact_moves_entries() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let mut array = None;
+        doc.transact(|tx| {
+            let a = tx.create_array()?;
+            tx.set(&ObjectRef(ROOT_ID), "list", Value::Array(a.clone()))?;
+            for i in 0..4 {
+                tx.push(&a, Value::Int(i))?;
+            }
+            array = Some(a);
+            Ok(())
+        })
+        .unwrap();
+        let array = array.unwrap();
+        doc.transact(|tx| {
+            tx.move_entry(&array, 0, &array, 3)?;
+            tx.move_entry(&array, 3, &array, 1)?;
+            tx.move_entry(&array, 2, &array, 2)?;
+            assert_eq!(
+                tx.move_entry(&array, 0, &array, 4),
+                Err(TreeError::IndexOutOfBounds)
+            );
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            array.to_vec(doc.tree()),
+            Ok(vec![
+                Value::Int(1),
+                Value::Int(0),
+                Value::Int(2),
+                Value::Int(3)
+            ])
+        );
+
+        // collections keep their contents when moved between arrays
+        doc.transact(|tx| {
+            let other = tx.create_array()?;
+            tx.set(&ObjectRef(ROOT_ID), "other", Value::Array(other.clone()))?;
+            let object = tx.create_object()?;
+            tx.push(&array, Value::Object(object.clone()))?;
+            tx.set(&object, "key", Value::True)?;
+            tx.move_entry(&array, 4, &other, 0)?;
+            // an array can't be moved inside itself
+            let nested = tx.create_array()?;
+            tx.push(&other, Value::Array(nested.clone()))?;
+            assert_eq!(
+                tx.move_entry(&other, 1, &nested, 0),
+                Err(TreeError::EditWouldCauseCycle)
+            );
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(array.len(doc.tree()), Ok(4));
+        match array_at(&doc, "other").get(doc.tree(), 0) {
+            Ok(Value::Object(object)) => {
+                assert_eq!(object.get(doc.tree(), "key"), Ok(Value::True));
+            }
+            v => panic!("expected object, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn failed_transaction_leaves_doc_unchanged() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let result = doc.transact(|tx| {
+            let array = tx.create_array()?;
+            tx.set(&ObjectRef(ROOT_ID), "list", Value::Array(array.clone()))?;
+            tx.insert(&array, 1, Value::Null)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(TransactError::Tree(TreeError::IndexOutOfBounds))
+        );
+        assert_eq!(
+            ObjectRef(ROOT_ID).get(doc.tree(), "list"),
+            Ok(json::Value::Unset)
+        );
+    }
+
+    #[test]
+    fn transaction_recovers_from_failed_edits() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let op = doc
+            .transact(|tx| {
+                let object = tx.create_object()?;
+                tx.set(&ObjectRef(ROOT_ID), "a", Value::Object(object.clone()))?;
+                let before = tx.tree().clone();
+                assert_eq!(
+                    tx.set(&ObjectRef(ROOT_ID), "b", Value::Object(object)),
+                    Err(TreeError::NodeAlreadyHadParent)
+                );
+                assert_eq!(
+                    before.to_json(&before.root_value()),
+                    tx.tree().to_json(&tx.tree().root_value())
+                );
+                tx.set(&ObjectRef(ROOT_ID), "b", Value::Int(1))
+            })
+            .unwrap();
+        let mut other = Doc::new_with_site(SiteId([2; 16]));
+        assert!(other.update(op).failures.is_empty());
+        assert_eq!(doc.to_json(), serde_json::json!({"a": {}, "b": 1}));
+        assert_eq!(doc.to_json(), other.to_json());
+    }
+
+    #[test]
+    fn import_json() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let json = serde_json::json!({
+            "name": "crudite",
+            "tags": ["crdt", 1, true, false, null, {"nested": []}],
+            "count": -3,
+        });
+        let op = doc.set_json(&ObjectRef(ROOT_ID), "doc", &json).unwrap();
+
+        // the whole value is imported in a single op
+        let mut other = Doc::new_with_site(SiteId([2; 16]));
+        assert!(other.update(op).failures.is_empty());
+
+        for doc in &[&doc, &other] {
+            let tree = doc.tree();
+            let object = match ObjectRef(ROOT_ID).get(tree, "doc").unwrap() {
+                Value::Object(o) => o,
+                v => panic!("expected object, got {:?}", v),
+            };
+            match object.get(tree, "name").unwrap() {
+                Value::String(s) => assert_eq!(s.to_string(tree).unwrap(), "crudite"),
+                v => panic!("expected string, got {:?}", v),
+            }
+            assert_eq!(object.get(tree, "count"), Ok(Value::Int(-3)));
+            let tags = match object.get(tree, "tags").unwrap() {
+                Value::Array(a) => a.to_vec(tree).unwrap(),
+                v => panic!("expected array, got {:?}", v),
+            };
+            assert_eq!(tags.len(), 6);
+            assert_eq!(
+                tags[1..5],
+                [Value::Int(1), Value::True, Value::False, Value::Null]
+            );
+            match &tags[5] {
+                Value::Object(o) => match o.get(tree, "nested").unwrap() {
+                    Value::Array(a) => assert_eq!(a.to_vec(tree), Ok(vec![])),
+                    v => panic!("expected array, got {:?}", v),
+                },
+                v => panic!("expected object, got {:?}", v),
+            }
+        }
+
+        let array = match ObjectRef(ROOT_ID).get(doc.tree(), "doc").unwrap() {
+            Value::Object(o) => match o.get(doc.tree(), "tags").unwrap() {
+                Value::Array(a) => a,
+                v => panic!("expected array, got {:?}", v),
+            },
+            v => panic!("expected object, got {:?}", v),
+        };
+        doc.insert_json(&array, 0, &serde_json::json!(7)).unwrap();
+        assert_eq!(array.to_vec(doc.tree()).unwrap()[0], Value::Int(7));
+
+        doc.insert_json(&array, 0, &serde_json::json!(1.5)).unwrap();
+        assert_eq!(array.to_vec(doc.tree()).unwrap()[0], Value::Float(1.5));
+        assert_eq!(
+            doc.insert_json(&array, 0, &serde_json::json!(u64::MAX)),
+            Err(TransactError::Tree(TreeError::UnsupportedValue))
+        );
+    }
+
+    #[test]
+    fn import_then_export_roundtrips() {
+        let json = serde_json::json!({
+            "z": [1, 2, {"b": "two", "a": "🎉"}],
+            "a": null,
+            "m": {"": false},
+            "p": [19.99, -0.5, 1e300],
+        });
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        for (key, value) in json.as_object().unwrap() {
+            doc.set_json(&ObjectRef(ROOT_ID), key, value).unwrap();
+        }
+        assert_eq!(doc.to_json(), json);
+        assert_eq!(
+            doc.to_json_string(),
+            r#"{"a":null,"m":{"":false},"p":[19.99,-0.5,1e+300],"z":[1,2,{"a":"🎉","b":"two"}]}"#
+        );
+    }
+}
@@ -0,0 +1,857 @@
+//! A compact binary encoding for batches of `DocOp`s.
+//!
+//! An encoded batch starts with a header containing `MAGIC` and `VERSION`, followed by a table of
+//! every `SiteId` referenced in the batch, and then the ops themselves. Integers are written as
+//! LEB128 varints, and floats as the 8 little-endian bytes of their bit pattern. Ids refer to
+//! sites by their index in the site table, and their `num` is stored as the difference from the
+//! previously encoded id's `num`, since ids allocated close together tend to have similar
+//! counters. Timestamp counters are likewise stored as the difference from the previous op's
+//! counter.
+//!
+//! Typing produces long chains of `TextInsert`s, each inserting after the previous one with the
+//! next id from the same site. These chains are run-length encoded as an anchor, a first id and
+//! the inserted text, the same way as a `TextInsertRun`, which makes them cost roughly one byte
+//! per character.
+
+use crate::doc::{DocOp, Id, SiteId, Timestamp};
+use crate::json::{self, Edit, Value};
+use std::collections::HashMap;
+
+/// Bytes that every encoded batch starts with.
+pub const MAGIC: [u8; 4] = *b"crud";
+/// Version of the encoding produced by `encode`.
+pub const VERSION: u8 = 1;
+
+const TAG_ARRAY_CREATE: u8 = 0;
+const TAG_ARRAY_INSERT: u8 = 1;
+const TAG_ARRAY_DELETE: u8 = 2;
+const TAG_MAP_CREATE: u8 = 3;
+const TAG_MAP_INSERT: u8 = 4;
+const TAG_TEXT_CREATE: u8 = 5;
+const TAG_TEXT_INSERT: u8 = 6;
+const TAG_TEXT_DELETE: u8 = 7;
+/// A chain of `TextInsert`s, each inserted after the previous one with consecutive ids.
+const TAG_TEXT_INSERT_CHAIN: u8 = 8;
+const TAG_TEXT_INSERT_RUN: u8 = 9;
+const TAG_TEXT_DELETE_RANGE: u8 = 10;
+const TAG_ARRAY_DELETE_RANGE: u8 = 11;
+const TAG_ARRAY_MOVE: u8 = 12;
+const TAG_MAP_INSERT_MULTI: u8 = 13;
+const TAG_COUNTER_CREATE: u8 = 14;
+const TAG_COUNTER_ADD: u8 = 15;
+const TAG_SET_CREATE: u8 = 16;
+const TAG_SET_ADD: u8 = 17;
+const TAG_SET_REMOVE: u8 = 18;
+const TAG_TEXT_MARK: u8 = 19;
+const TAG_TEXT_INSERT_EMBED: u8 = 20;
+
+const VALUE_UNSET: u8 = 0;
+const VALUE_NULL: u8 = 1;
+const VALUE_TRUE: u8 = 2;
+const VALUE_FALSE: u8 = 3;
+const VALUE_INT: u8 = 4;
+const VALUE_STRING: u8 = 5;
+const VALUE_ARRAY: u8 = 6;
+const VALUE_OBJECT: u8 = 7;
+const VALUE_FLOAT: u8 = 8;
+const VALUE_COUNTER: u8 = 9;
+const VALUE_SET: u8 = 10;
+const VALUE_BYTES: u8 = 11;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input did not start with `MAGIC`.
+    BadMagic,
+    /// The input was encoded with a version of the format this library can't read.
+    UnsupportedVersion(u8),
+    /// The input ended in the middle of a value.
+    UnexpectedEnd,
+    /// There were bytes left over after the last op.
+    TrailingBytes,
+    /// A varint was too large to fit in 64 bits.
+    VarintOverflow,
+    /// An edit or value had an unknown type tag.
+    UnknownTag(u8),
+    /// An id referred to a site that isn't in the site table.
+    UnknownSite,
+    /// A string or character was not valid UTF-8.
+    InvalidUtf8,
+    /// The value of a `TextMark` was not valid JSON.
+    InvalidJson,
+}
+
+/// Encodes a batch of ops.
+pub fn encode(ops: &[DocOp]) -> Vec<u8> {
+    let mut encoder = Encoder {
+        buf: Vec::new(),
+        sites: HashMap::new(),
+        prev_num: 0,
+    };
+    encoder.buf.extend_from_slice(&MAGIC);
+    encoder.buf.push(VERSION);
+
+    // build site table
+    let mut site_list = Vec::new();
+    let mut add_site = |site: &SiteId, sites: &mut HashMap<SiteId, u64>| {
+        if !sites.contains_key(site) {
+            sites.insert(*site, site_list.len() as u64);
+            site_list.push(*site);
+        }
+    };
+    for op in ops {
+        add_site(&op.timestamp.site, &mut encoder.sites);
+        for edit in &op.edits {
+            for id in edit.ids() {
+                add_site(&id.site, &mut encoder.sites);
+            }
+        }
+    }
+    encoder.write_varint(site_list.len() as u64);
+    for site in &site_list {
+        encoder.buf.extend_from_slice(&site.0);
+    }
+
+    encoder.write_varint(ops.len() as u64);
+    let mut prev_counter = 0;
+    for op in ops {
+        encoder.write_delta(prev_counter, op.timestamp.counter);
+        prev_counter = op.timestamp.counter;
+        encoder.write_varint(encoder.sites[&op.timestamp.site]);
+        encoder.write_edits(&op.edits);
+    }
+    encoder.buf
+}
+
+/// Decodes a batch of ops produced by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<Vec<DocOp>, DecodeError> {
+    let mut decoder = Decoder {
+        bytes,
+        pos: 0,
+        sites: Vec::new(),
+        prev_num: 0,
+    };
+    if decoder
+        .read_bytes(MAGIC.len())
+        .map_err(|_| DecodeError::BadMagic)?
+        != MAGIC
+    {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = decoder.read_byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let site_count = decoder.read_varint()?;
+    for _ in 0..site_count {
+        let mut site = [0; 16];
+        site.copy_from_slice(decoder.read_bytes(16)?);
+        decoder.sites.push(SiteId(site));
+    }
+
+    let op_count = decoder.read_varint()?;
+    let mut ops = Vec::new();
+    let mut prev_counter = 0;
+    for _ in 0..op_count {
+        let counter = decoder.read_delta(prev_counter)?;
+        prev_counter = counter;
+        let site = decoder.read_site()?;
+        let edits = decoder.read_edits()?;
+        ops.push(DocOp {
+            timestamp: Timestamp { counter, site },
+            edits,
+        });
+    }
+    if decoder.pos != bytes.len() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(ops)
+}
+
+/// Whether `edit` is a `TextInsert` that continues a chain of inserts ending at `prev`.
+fn continues_chain(prev: &Id, edit: &Edit<Id>) -> bool {
+    match edit {
+        Edit::TextInsert { index, id, .. } => {
+            &index.0 == prev && id.0.site == prev.site && Some(id.0.num) == prev.num.checked_add(1)
+        }
+        _ => false,
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+    /// Maps sites to their index in the site table.
+    sites: HashMap<SiteId, u64>,
+    /// `num` of the last id written.
+    prev_num: u64,
+}
+
+impl Encoder {
+    fn write_varint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    fn write_delta(&mut self, prev: u64, n: u64) {
+        self.write_varint(zigzag(n.wrapping_sub(prev) as i64));
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_varint(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn write_id(&mut self, id: &Id) {
+        self.write_varint(self.sites[&id.site]);
+        self.write_delta(self.prev_num, id.num);
+        self.prev_num = id.num;
+    }
+
+    fn write_id_ranges(&mut self, ranges: &[json::IdRange<Id>]) {
+        self.write_varint(ranges.len() as u64);
+        for range in ranges {
+            self.write_id(&range.start);
+            self.write_varint(range.len as u64);
+            // like a `TextInsertRun`, leave `prev_num` at the last id of the run
+            self.prev_num = range
+                .start
+                .num
+                .wrapping_add(range.len.saturating_sub(1) as u64);
+        }
+    }
+
+    fn write_value(&mut self, value: &Value<Id>) {
+        match value {
+            Value::Unset => self.buf.push(VALUE_UNSET),
+            Value::Null => self.buf.push(VALUE_NULL),
+            Value::True => self.buf.push(VALUE_TRUE),
+            Value::False => self.buf.push(VALUE_FALSE),
+            Value::Int(i) => {
+                self.buf.push(VALUE_INT);
+                self.write_varint(zigzag(*i));
+            }
+            Value::Float(f) => {
+                self.buf.push(VALUE_FLOAT);
+                self.buf.extend_from_slice(&f.to_bits().to_le_bytes());
+            }
+            Value::Bytes(bytes) => {
+                self.buf.push(VALUE_BYTES);
+                self.write_varint(bytes.len() as u64);
+                self.buf.extend_from_slice(bytes);
+            }
+            Value::String(json::StringRef(id)) => {
+                self.buf.push(VALUE_STRING);
+                self.write_id(id);
+            }
+            Value::Array(json::ArrayRef(id)) => {
+                self.buf.push(VALUE_ARRAY);
+                self.write_id(id);
+            }
+            Value::Object(json::ObjectRef(id)) => {
+                self.buf.push(VALUE_OBJECT);
+                self.write_id(id);
+            }
+            Value::Counter(json::CounterRef(id)) => {
+                self.buf.push(VALUE_COUNTER);
+                self.write_id(id);
+            }
+            Value::Set(json::SetRef(id)) => {
+                self.buf.push(VALUE_SET);
+                self.write_id(id);
+            }
+        }
+    }
+
+    /// Writes `edits`, prefixed by the number of encoded entries. Chains of `TextInsert`s are
+    /// merged into a single entry.
+    fn write_edits(&mut self, edits: &[Edit<Id>]) {
+        let mut entries = Vec::new();
+        let mut i = 0;
+        while i < edits.len() {
+            let mut end = i + 1;
+            if let Edit::TextInsert { id, .. } = &edits[i] {
+                let mut prev = &id.0;
+                while let Some(Edit::TextInsert { id, .. }) = edits.get(end) {
+                    if !continues_chain(prev, &edits[end]) {
+                        break;
+                    }
+                    prev = &id.0;
+                    end += 1;
+                }
+            }
+            entries.push(&edits[i..end]);
+            i = end;
+        }
+
+        self.write_varint(entries.len() as u64);
+        for entry in entries {
+            if entry.len() > 1 {
+                self.write_text_insert_chain(entry);
+            } else {
+                self.write_edit(&entry[0]);
+            }
+        }
+    }
+
+    fn write_text_insert_chain(&mut self, chain: &[Edit<Id>]) {
+        let mut text = String::new();
+        for edit in chain {
+            if let Edit::TextInsert { character, .. } = edit {
+                text.push(*character);
+            }
+        }
+        if let Edit::TextInsert { index, id, .. } = &chain[0] {
+            self.buf.push(TAG_TEXT_INSERT_CHAIN);
+            self.write_id(&index.0);
+            self.write_id(&id.0);
+            // the rest of the ids are implied, so leave `prev_num` at the last one
+            self.prev_num = id.0.num + chain.len() as u64 - 1;
+            self.write_str(&text);
+        }
+    }
+
+    fn write_edit(&mut self, edit: &Edit<Id>) {
+        match edit {
+            Edit::ArrayCreate { id } => {
+                self.buf.push(TAG_ARRAY_CREATE);
+                self.write_id(&id.0);
+            }
+            Edit::ArrayInsert { index, id, item } => {
+                self.buf.push(TAG_ARRAY_INSERT);
+                self.write_id(&index.0);
+                self.write_id(&id.0);
+                self.write_value(item);
+            }
+            Edit::ArrayDelete { id } => {
+                self.buf.push(TAG_ARRAY_DELETE);
+                self.write_id(&id.0);
+            }
+            Edit::ArrayMove { id, index, new_id } => {
+                self.buf.push(TAG_ARRAY_MOVE);
+                self.write_id(&id.0);
+                self.write_id(&index.0);
+                self.write_id(&new_id.0);
+            }
+            Edit::MapCreate { id } => {
+                self.buf.push(TAG_MAP_CREATE);
+                self.write_id(&id.0);
+            }
+            Edit::MapInsert { parent, key, item } => {
+                self.buf.push(TAG_MAP_INSERT);
+                self.write_id(&parent.0);
+                self.write_str(key);
+                self.write_value(item);
+            }
+            Edit::MapInsertMulti {
+                parent,
+                key,
+                id,
+                item,
+                supersedes,
+            } => {
+                self.buf.push(TAG_MAP_INSERT_MULTI);
+                self.write_id(&parent.0);
+                self.write_str(key);
+                self.write_id(id);
+                self.write_value(item);
+                self.write_varint(supersedes.len() as u64);
+                for id in supersedes {
+                    self.write_id(id);
+                }
+            }
+            Edit::CounterCreate { id } => {
+                self.buf.push(TAG_COUNTER_CREATE);
+                self.write_id(&id.0);
+            }
+            Edit::CounterAdd { id, amount } => {
+                self.buf.push(TAG_COUNTER_ADD);
+                self.write_id(&id.0);
+                self.write_varint(zigzag(*amount));
+            }
+            Edit::SetCreate { id } => {
+                self.buf.push(TAG_SET_CREATE);
+                self.write_id(&id.0);
+            }
+            Edit::SetAdd { id, tag, item } => {
+                self.buf.push(TAG_SET_ADD);
+                self.write_id(&id.0);
+                self.write_id(tag);
+                self.write_value(item);
+            }
+            Edit::SetRemove { id, item, observed } => {
+                self.buf.push(TAG_SET_REMOVE);
+                self.write_id(&id.0);
+                self.write_value(item);
+                self.write_varint(observed.len() as u64);
+                for tag in observed {
+                    self.write_id(tag);
+                }
+            }
+            Edit::TextCreate { id } => {
+                self.buf.push(TAG_TEXT_CREATE);
+                self.write_id(&id.0);
+            }
+            Edit::TextInsert {
+                index,
+                id,
+                character,
+            } => {
+                self.buf.push(TAG_TEXT_INSERT);
+                self.write_id(&index.0);
+                self.write_id(&id.0);
+                self.write_varint(*character as u64);
+            }
+            Edit::TextDelete { id } => {
+                self.buf.push(TAG_TEXT_DELETE);
+                self.write_id(&id.0);
+            }
+            Edit::TextInsertRun { index, id, text } => {
+                self.buf.push(TAG_TEXT_INSERT_RUN);
+                self.write_id(&index.0);
+                self.write_id(&id.0);
+                // the rest of the ids are implied, so leave `prev_num` at the last one
+                let len = text.chars().count() as u64;
+                self.prev_num = id.0.num.wrapping_add(len.saturating_sub(1));
+                self.write_str(text);
+            }
+            Edit::TextDeleteRange { ids } => {
+                self.buf.push(TAG_TEXT_DELETE_RANGE);
+                self.write_id_ranges(ids);
+            }
+            Edit::ArrayDeleteRange { ids } => {
+                self.buf.push(TAG_ARRAY_DELETE_RANGE);
+                self.write_id_ranges(ids);
+            }
+            Edit::TextInsertEmbed { index, id, item } => {
+                self.buf.push(TAG_TEXT_INSERT_EMBED);
+                self.write_id(&index.0);
+                self.write_id(&id.0);
+                self.write_value(item);
+            }
+            Edit::TextMark {
+                start,
+                end,
+                expand,
+                name,
+                value,
+            } => {
+                self.buf.push(TAG_TEXT_MARK);
+                self.write_id(&start.0);
+                self.write_id(&end.0);
+                self.buf.push(match expand {
+                    json::Expand::None => 0,
+                    json::Expand::Before => 1,
+                    json::Expand::After => 2,
+                    json::Expand::Both => 3,
+                });
+                self.write_str(name);
+                self.write_str(&value.to_string());
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// The site table.
+    sites: Vec<SiteId>,
+    /// `num` of the last id read.
+    prev_num: u64,
+}
+
+impl<'a> Decoder<'a> {
+    fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.bytes.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut n: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+                return Err(DecodeError::VarintOverflow);
+            }
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_delta(&mut self, prev: u64) -> Result<u64, DecodeError> {
+        Ok(prev.wrapping_add(unzigzag(self.read_varint()?) as u64))
+    }
+
+    fn read_str(&mut self) -> Result<String, DecodeError> {
+        let len = self.read_varint()? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn read_site(&mut self) -> Result<SiteId, DecodeError> {
+        let index = self.read_varint()?;
+        self.sites
+            .get(index as usize)
+            .copied()
+            .ok_or(DecodeError::UnknownSite)
+    }
+
+    fn read_id(&mut self) -> Result<Id, DecodeError> {
+        let site = self.read_site()?;
+        let num = self.read_delta(self.prev_num)?;
+        self.prev_num = num;
+        Ok(Id { site, num })
+    }
+
+    fn read_id_ranges(&mut self) -> Result<Vec<json::IdRange<Id>>, DecodeError> {
+        let count = self.read_varint()?;
+        (0..count)
+            .map(|_| {
+                let start = self.read_id()?;
+                let len = self.read_varint()? as usize;
+                self.prev_num = start.num.wrapping_add(len.saturating_sub(1) as u64);
+                Ok(json::IdRange { start, len })
+            })
+            .collect()
+    }
+
+    fn read_value(&mut self) -> Result<Value<Id>, DecodeError> {
+        Ok(match self.read_byte()? {
+            VALUE_UNSET => Value::Unset,
+            VALUE_NULL => Value::Null,
+            VALUE_TRUE => Value::True,
+            VALUE_FALSE => Value::False,
+            VALUE_INT => Value::Int(unzigzag(self.read_varint()?)),
+            VALUE_FLOAT => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(self.read_bytes(8)?);
+                Value::Float(f64::from_bits(u64::from_le_bytes(bits)))
+            }
+            VALUE_BYTES => {
+                let len = self.read_varint()? as usize;
+                Value::Bytes(self.read_bytes(len)?.to_vec())
+            }
+            VALUE_STRING => Value::String(json::StringRef(self.read_id()?)),
+            VALUE_ARRAY => Value::Array(json::ArrayRef(self.read_id()?)),
+            VALUE_OBJECT => Value::Object(json::ObjectRef(self.read_id()?)),
+            VALUE_COUNTER => Value::Counter(json::CounterRef(self.read_id()?)),
+            VALUE_SET => Value::Set(json::SetRef(self.read_id()?)),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+
+    fn read_edits(&mut self) -> Result<Vec<Edit<Id>>, DecodeError> {
+        let entry_count = self.read_varint()?;
+        let mut edits = Vec::new();
+        for _ in 0..entry_count {
+            let edit = match self.read_byte()? {
+                TAG_ARRAY_CREATE => Edit::ArrayCreate {
+                    id: json::ArrayRef(self.read_id()?),
+                },
+                TAG_ARRAY_INSERT => Edit::ArrayInsert {
+                    index: json::ArrayIndex(self.read_id()?),
+                    id: json::ArrayIndex(self.read_id()?),
+                    item: self.read_value()?,
+                },
+                TAG_ARRAY_DELETE => Edit::ArrayDelete {
+                    id: json::ArrayIndex(self.read_id()?),
+                },
+                TAG_ARRAY_MOVE => Edit::ArrayMove {
+                    id: json::ArrayIndex(self.read_id()?),
+                    index: json::ArrayIndex(self.read_id()?),
+                    new_id: json::ArrayIndex(self.read_id()?),
+                },
+                TAG_MAP_CREATE => Edit::MapCreate {
+                    id: json::ObjectRef(self.read_id()?),
+                },
+                TAG_MAP_INSERT => Edit::MapInsert {
+                    parent: json::ObjectRef(self.read_id()?),
+                    key: self.read_str()?,
+                    item: self.read_value()?,
+                },
+                TAG_MAP_INSERT_MULTI => Edit::MapInsertMulti {
+                    parent: json::ObjectRef(self.read_id()?),
+                    key: self.read_str()?,
+                    id: self.read_id()?,
+                    item: self.read_value()?,
+                    supersedes: {
+                        let count = self.read_varint()?;
+                        (0..count)
+                            .map(|_| self.read_id())
+                            .collect::<Result<_, _>>()?
+                    },
+                },
+                TAG_COUNTER_CREATE => Edit::CounterCreate {
+                    id: json::CounterRef(self.read_id()?),
+                },
+                TAG_COUNTER_ADD => Edit::CounterAdd {
+                    id: json::CounterRef(self.read_id()?),
+                    amount: unzigzag(self.read_varint()?),
+                },
+                TAG_SET_CREATE => Edit::SetCreate {
+                    id: json::SetRef(self.read_id()?),
+                },
+                TAG_SET_ADD => Edit::SetAdd {
+                    id: json::SetRef(self.read_id()?),
+                    tag: self.read_id()?,
+                    item: self.read_value()?,
+                },
+                TAG_SET_REMOVE => Edit::SetRemove {
+                    id: json::SetRef(self.read_id()?),
+                    item: self.read_value()?,
+                    observed: {
+                        let count = self.read_varint()?;
+                        (0..count)
+                            .map(|_| self.read_id())
+                            .collect::<Result<_, _>>()?
+                    },
+                },
+                TAG_TEXT_CREATE => Edit::TextCreate {
+                    id: json::StringRef(self.read_id()?),
+                },
+                TAG_TEXT_INSERT => Edit::TextInsert {
+                    index: json::StringIndex(self.read_id()?),
+                    id: json::StringIndex(self.read_id()?),
+                    character: self.read_char()?,
+                },
+                TAG_TEXT_DELETE => Edit::TextDelete {
+                    id: json::StringIndex(self.read_id()?),
+                },
+                TAG_TEXT_INSERT_RUN => {
+                    let index = json::StringIndex(self.read_id()?);
+                    let id = json::StringIndex(self.read_id()?);
+                    let text = self.read_str()?;
+                    let len = text.chars().count() as u64;
+                    self.prev_num = id.0.num.wrapping_add(len.saturating_sub(1));
+                    Edit::TextInsertRun { index, id, text }
+                }
+                TAG_TEXT_DELETE_RANGE => Edit::TextDeleteRange {
+                    ids: self.read_id_ranges()?,
+                },
+                TAG_ARRAY_DELETE_RANGE => Edit::ArrayDeleteRange {
+                    ids: self.read_id_ranges()?,
+                },
+                TAG_TEXT_INSERT_EMBED => Edit::TextInsertEmbed {
+                    index: json::StringIndex(self.read_id()?),
+                    id: json::StringIndex(self.read_id()?),
+                    item: self.read_value()?,
+                },
+                TAG_TEXT_MARK => Edit::TextMark {
+                    start: json::StringIndex(self.read_id()?),
+                    end: json::StringIndex(self.read_id()?),
+                    expand: match self.read_byte()? {
+                        0 => json::Expand::None,
+                        1 => json::Expand::Before,
+                        2 => json::Expand::After,
+                        3 => json::Expand::Both,
+                        tag => return Err(DecodeError::UnknownTag(tag)),
+                    },
+                    name: self.read_str()?,
+                    value: serde_json::from_str(&self.read_str()?)
+                        .map_err(|_| DecodeError::InvalidJson)?,
+                },
+                TAG_TEXT_INSERT_CHAIN => {
+                    self.read_text_insert_chain(&mut edits)?;
+                    continue;
+                }
+                tag => return Err(DecodeError::UnknownTag(tag)),
+            };
+            edits.push(edit);
+        }
+        Ok(edits)
+    }
+
+    fn read_char(&mut self) -> Result<char, DecodeError> {
+        let n = self.read_varint()?;
+        std::char::from_u32(n as u32)
+            .filter(|_| n <= u64::from(u32::MAX))
+            .ok_or(DecodeError::InvalidUtf8)
+    }
+
+    fn read_text_insert_chain(&mut self, edits: &mut Vec<Edit<Id>>) -> Result<(), DecodeError> {
+        let mut prev = self.read_id()?;
+        let first = self.read_id()?;
+        let text = self.read_str()?;
+        for (i, character) in text.chars().enumerate() {
+            let id = Id {
+                site: first.site,
+                num: first.num.wrapping_add(i as u64),
+            };
+            edits.push(Edit::TextInsert {
+                index: json::StringIndex(prev),
+                id: json::StringIndex(id.clone()),
+                character,
+            });
+            prev = id;
+        }
+        self.prev_num = prev.num;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::doc::{Doc, ROOT_ID};
+
+    fn editing_session(doc: &mut Doc, text: &str) -> DocOp {
+        doc.transact(|tx| {
+            let string = tx.create_string()?;
+            tx.set(
+                &json::ObjectRef(ROOT_ID),
+                "text",
+                Value::String(string.clone()),
+            )?;
+            tx.insert_text(&string, 0, text)?;
+            tx.insert_text(&string, 1, "🎉")?;
+            tx.delete_text(&string, 2, 1)?;
+            tx.mark_text(
+                &string,
+                0,
+                2,
+                "link",
+                serde_json::json!({ "href": "/" }),
+                json::Expand::None,
+            )?;
+            tx.mark_text(&string, 1, 1, "bold", true.into(), json::Expand::After)?;
+            let mention = tx.create_object()?;
+            tx.insert_embed(&string, 1, Value::Object(mention))?;
+            let array = tx.create_array()?;
+            tx.set(
+                &json::ObjectRef(ROOT_ID),
+                "list",
+                Value::Array(array.clone()),
+            )?;
+            tx.push(&array, Value::Int(-100))?;
+            tx.push(&array, Value::Float(-2.5))?;
+            tx.push(&array, Value::Bytes(vec![0, 1, 255]))?;
+            let counter = tx.create_counter()?;
+            tx.push(&array, Value::Counter(counter.clone()))?;
+            tx.increment(&counter, -7)?;
+            let set = tx.create_set()?;
+            tx.push(&array, Value::Set(set.clone()))?;
+            tx.add_member(&set, Value::Int(3))?;
+            tx.add_member(&set, Value::True)?;
+            tx.remove_member(&set, &Value::Int(3))?;
+            tx.push(&array, Value::Null)?;
+            tx.push(&array, Value::Null)?;
+            tx.remove_range(&array, 1, 2)?;
+            tx.push(&array, Value::Null)?;
+            tx.move_entry(&array, 0, &array, 1)?;
+            tx.push(&array, Value::True)?;
+            tx.push(&array, Value::False)?;
+            let object = tx.create_object()?;
+            tx.push(&array, Value::Object(object.clone()))?;
+            tx.set(&object, "unset", Value::Unset)?;
+            tx.set_multi(&object, "multi", Value::Int(1))?;
+            tx.set_multi(&object, "multi", Value::Int(2))?;
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut doc_a = Doc::new_with_site(SiteId([1; 16]));
+        let mut doc_b = Doc::new_with_site(SiteId([2; 16]));
+        let mut ops = vec![
+            editing_session(&mut doc_a, "hello world"),
+            editing_session(&mut doc_b, "abc"),
+        ];
+        ops.push(DocOp {
+            timestamp: doc_b.next_timestamp(),
+            edits: vec![],
+        });
+
+        let encoded = encode(&ops);
+        assert_eq!(&encoded[..4], &MAGIC);
+        assert_eq!(encoded[4], VERSION);
+        assert_eq!(decode(&encoded), Ok(ops));
+    }
+
+    #[test]
+    fn text_inserts_are_compact() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let text = "a".repeat(10_000);
+        let ops = vec![editing_session(&mut doc, &text)];
+        let encoded = encode(&ops);
+        assert!(encoded.len() < text.len() + 400);
+        assert_eq!(decode(&encoded), Ok(ops));
+
+        // characters typed one at a time, each inserted after the last
+        let string = doc.next_id();
+        let mut prev = string.clone();
+        let mut edits = vec![Edit::TextCreate {
+            id: json::StringRef(string),
+        }];
+        for _ in 0..10_000 {
+            let id = doc.next_id();
+            edits.push(Edit::TextInsert {
+                index: json::StringIndex(prev),
+                id: json::StringIndex(id.clone()),
+                character: 'a',
+            });
+            prev = id;
+        }
+        let ops = vec![DocOp {
+            timestamp: doc.next_timestamp(),
+            edits,
+        }];
+        let encoded = encode(&ops);
+        assert!(encoded.len() < text.len() + 200);
+        assert_eq!(decode(&encoded), Ok(ops));
+    }
+
+    #[test]
+    fn invalid_input_errors() {
+        let mut doc = Doc::new_with_site(SiteId([1; 16]));
+        let encoded = encode(&[editing_session(&mut doc, "hi")]);
+
+        assert_eq!(decode(b"nope!"), Err(DecodeError::BadMagic));
+        assert_eq!(decode(b"cru"), Err(DecodeError::BadMagic));
+        let mut future = encoded.clone();
+        future[4] = VERSION + 1;
+        assert_eq!(
+            decode(&future),
+            Err(DecodeError::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(DecodeError::TrailingBytes));
+    }
+}